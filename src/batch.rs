@@ -0,0 +1,172 @@
+use anyhow::anyhow;
+
+use crate::segment_payload::SegmentPayload;
+
+// Segment HTTP Tracking API limits
+// see https://segment.com/docs/connections/sources/catalog/libraries/server/http-api/#max-request-size
+const MAX_MESSAGE_SIZE: usize = 32 * 1024;
+const MAX_BATCH_SIZE: usize = 500 * 1024;
+
+/// Segment messages derived from a single Edgee event, sent in a single request.
+///
+/// Required messages make the whole request fail when they break the Segment size limits,
+/// while derived messages (group, lifecycle events, ...) are dropped instead.
+#[derive(Debug, Default)]
+pub(crate) struct SegmentBatch {
+    messages: Vec<(SegmentPayload, bool)>,
+}
+
+impl SegmentBatch {
+    pub(crate) fn new(segment_payload: SegmentPayload) -> Self {
+        let mut batch = SegmentBatch::default();
+        batch.push(segment_payload);
+        batch
+    }
+
+    /// Add a message that must be delivered.
+    pub(crate) fn push(&mut self, segment_payload: SegmentPayload) {
+        self.messages.push((segment_payload, true));
+    }
+
+    /// Add a message that is only delivered if it fits in the Segment limits.
+    pub(crate) fn push_derived(&mut self, segment_payload: SegmentPayload) {
+        self.messages.push((segment_payload, false));
+    }
+
+    /// Whether the privacy profile applies to any of the messages.
+    pub(crate) fn is_anonymized(&self) -> bool {
+        self.messages
+            .iter()
+            .any(|(segment_payload, _)| segment_payload.is_anonymized())
+    }
+
+    /// Serialize the messages, returning the endpoint path and the request body.
+    ///
+    /// Anonymized messages are stripped of the user ids and traits set after their creation,
+    /// and personal data is scrubbed from all messages when enabled.
+    ///
+    /// A single message goes to the endpoint of its call type, several messages are sent
+    /// together to the `/v1/batch` endpoint.
+    pub(crate) fn build(mut self) -> anyhow::Result<(&'static str, String)> {
+        let mut serialized = Vec::with_capacity(self.messages.len());
+        for (segment_payload, required) in self.messages.iter_mut() {
+            segment_payload.anonymize();
+            segment_payload.scrub();
+            let body = serde_json::to_string(segment_payload)?;
+            if body.len() > MAX_MESSAGE_SIZE {
+                if *required {
+                    return Err(anyhow!(
+                        "Segment {} message exceeds the 32KB limit",
+                        segment_payload.event_type
+                    ));
+                }
+                continue;
+            }
+            serialized.push((segment_payload.endpoint(), body, *required));
+        }
+
+        // `{"batch":[...]}` plus a separating comma per message
+        let overhead = r#"{"batch":[]}"#.len();
+        let mut size = overhead
+            + serialized
+                .iter()
+                .filter(|(_, _, required)| *required)
+                .map(|(_, body, _)| body.len() + 1)
+                .sum::<usize>();
+        if size > MAX_BATCH_SIZE {
+            return Err(anyhow!("Segment batch exceeds the 500KB limit"));
+        }
+        serialized.retain(|(_, body, required)| {
+            if *required {
+                return true;
+            }
+            if size + body.len() + 1 > MAX_BATCH_SIZE {
+                return false;
+            }
+            size += body.len() + 1;
+            true
+        });
+
+        match serialized.len() {
+            0 => Err(anyhow!("No Segment message to send")),
+            1 => {
+                let (endpoint, body, _) = serialized.remove(0);
+                Ok((endpoint, body))
+            }
+            _ => {
+                let messages: Vec<String> =
+                    serialized.into_iter().map(|(_, body, _)| body).collect();
+                Ok((
+                    "/v1/batch",
+                    format!(r#"{{"batch":[{}]}}"#, messages.join(",")),
+                ))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use std::collections::HashMap;
+
+    fn sample_payload(event_type: &str, property_size: usize) -> SegmentPayload {
+        let mut segment_payload = SegmentPayload::default();
+        segment_payload.event_type = event_type.to_string();
+        segment_payload.properties = Some(HashMap::from([(
+            "padding".to_string(),
+            "x".repeat(property_size).into(),
+        )]));
+        segment_payload
+    }
+
+    #[test]
+    fn single_message_uses_call_type_endpoint() {
+        let (endpoint, body) = SegmentBatch::new(sample_payload("page", 10))
+            .build()
+            .unwrap();
+        assert_eq!(endpoint, "/v1/page");
+        let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(body["type"], "page");
+    }
+
+    #[test]
+    fn several_messages_use_batch_endpoint() {
+        let mut batch = SegmentBatch::new(sample_payload("identify", 10));
+        batch.push_derived(sample_payload("page", 10));
+        let (endpoint, body) = batch.build().unwrap();
+        assert_eq!(endpoint, "/v1/batch");
+        let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(body["batch"][0]["type"], "identify");
+        assert_eq!(body["batch"][1]["type"], "page");
+    }
+
+    #[test]
+    fn oversized_required_message_fails() {
+        let result = SegmentBatch::new(sample_payload("track", MAX_MESSAGE_SIZE)).build();
+        assert_eq!(result.is_err(), true);
+    }
+
+    #[test]
+    fn oversized_derived_message_is_dropped() {
+        let mut batch = SegmentBatch::new(sample_payload("track", 10));
+        batch.push_derived(sample_payload("group", MAX_MESSAGE_SIZE));
+        let (endpoint, _) = batch.build().unwrap();
+        assert_eq!(endpoint, "/v1/track");
+    }
+
+    #[test]
+    fn derived_messages_beyond_batch_limit_are_dropped() {
+        let mut batch = SegmentBatch::new(sample_payload("track", 30 * 1024));
+        for _ in 0..20 {
+            batch.push_derived(sample_payload("group", 30 * 1024));
+        }
+        let (endpoint, body) = batch.build().unwrap();
+        assert_eq!(endpoint, "/v1/batch");
+        assert!(body.len() <= MAX_BATCH_SIZE);
+        let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(body["batch"].as_array().unwrap().len(), 16);
+        assert_eq!(body["batch"][0]["type"], "track");
+    }
+}
@@ -0,0 +1,481 @@
+use anyhow::anyhow;
+use chrono::{DateTime, NaiveDate, SecondsFormat, TimeZone, Utc};
+use std::collections::HashMap;
+
+use crate::segment_payload::setting_enabled;
+
+/// Type a property value can be declared with in `segment_property_types`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ValueType {
+    String,
+    Int,
+    Float,
+    Bool,
+    Json,
+    Date,
+}
+
+impl ValueType {
+    fn parse(name: &str) -> Option<Self> {
+        match name.trim().to_lowercase().as_str() {
+            "string" => Some(ValueType::String),
+            "int" => Some(ValueType::Int),
+            "float" => Some(ValueType::Float),
+            "bool" => Some(ValueType::Bool),
+            "json" => Some(ValueType::Json),
+            "date" => Some(ValueType::Date),
+            _ => None,
+        }
+    }
+}
+
+/// Converts Edgee string properties into typed Segment values.
+///
+/// Keys declared in `segment_property_types` (`key:type` pairs separated by commas, with
+/// `string`, `int`, `float`, `bool`, `json` or `date` types) are converted to their type,
+/// other values are inferred. A value that cannot be converted is kept as a string.
+///
+/// When `segment_parse_json_properties` is enabled, undeclared values holding a JSON object
+/// or array are decoded. When `segment_unflatten_properties` is enabled, dotted keys are
+/// turned into nested objects.
+#[derive(Debug, Default)]
+pub(crate) struct Coercion {
+    types: HashMap<String, ValueType>,
+    parse_json: bool,
+    unflatten: bool,
+}
+
+impl Coercion {
+    pub(crate) fn new(settings: &HashMap<String, String>) -> anyhow::Result<Self> {
+        let mut coercion = Coercion {
+            parse_json: setting_enabled(settings, "segment_parse_json_properties"),
+            unflatten: setting_enabled(settings, "segment_unflatten_properties"),
+            ..Coercion::default()
+        };
+        let Some(declarations) = settings.get("segment_property_types") else {
+            return Ok(coercion);
+        };
+
+        for declaration in declarations.split(',').filter(|d| !d.trim().is_empty()) {
+            let Some((key, type_name)) = declaration.split_once(':') else {
+                return Err(anyhow!(
+                    "Segment property type `{declaration}` must be declared as `key:type`"
+                ));
+            };
+            let Some(value_type) = ValueType::parse(type_name) else {
+                return Err(anyhow!(
+                    "Segment property type `{type_name}` is not supported (string, int, float, bool, json, date)"
+                ));
+            };
+            coercion.types.insert(key.trim().to_string(), value_type);
+        }
+
+        Ok(coercion)
+    }
+
+    /// Convert the value of the given property key.
+    pub(crate) fn coerce(&self, key: &str, value: &str) -> serde_json::Value {
+        let coerced = match self.types.get(key) {
+            None if self.parse_json => return parse_json(value).unwrap_or_else(|| infer(value)),
+            None => return infer(value),
+            Some(ValueType::String) => None,
+            Some(ValueType::Int) => to_int(value),
+            Some(ValueType::Float) => to_float(value),
+            Some(ValueType::Bool) => to_bool(value),
+            Some(ValueType::Json) => serde_json::from_str(value).ok(),
+            Some(ValueType::Date) => to_date(value),
+        };
+        coerced.unwrap_or_else(|| value.into())
+    }
+
+    /// Turn dotted keys (`address.city`) into nested objects, if enabled.
+    ///
+    /// Dotted keys are applied in lexicographic order and merged into the objects of the
+    /// same name, overriding their fields. A dotted key that would need to go through a
+    /// non-object value (e.g. `address.city` when `address` is a string) is kept as is.
+    pub(crate) fn unflatten(
+        &self,
+        properties: HashMap<String, serde_json::Value>,
+    ) -> HashMap<String, serde_json::Value> {
+        if !self.unflatten {
+            return properties;
+        }
+
+        let (mut dotted, flat): (Vec<_>, Vec<_>) = properties
+            .into_iter()
+            .partition(|(key, _)| key.contains('.') && !key.split('.').any(str::is_empty));
+        dotted.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+        let mut unflattened: serde_json::Map<String, serde_json::Value> =
+            flat.into_iter().collect();
+        for (key, value) in dotted {
+            let path: Vec<&str> = key.split('.').collect();
+            if can_insert_path(&unflattened, &path) {
+                insert_path(&mut unflattened, &path, value);
+            } else {
+                unflattened.insert(key, value);
+            }
+        }
+        unflattened.into_iter().collect()
+    }
+}
+
+/// Decode a JSON object or array, leaving scalars to the type inference.
+fn parse_json(value: &str) -> Option<serde_json::Value> {
+    if !value.trim_start().starts_with(['{', '[']) {
+        return None;
+    }
+    serde_json::from_str(value)
+        .ok()
+        .filter(|value: &serde_json::Value| value.is_object() || value.is_array())
+}
+
+/// Whether all the parents of the path are objects or missing.
+fn can_insert_path(map: &serde_json::Map<String, serde_json::Value>, path: &[&str]) -> bool {
+    match path {
+        [] | [_] => true,
+        [parent, rest @ ..] => match map.get(*parent) {
+            None => true,
+            Some(serde_json::Value::Object(child)) => can_insert_path(child, rest),
+            Some(_) => false,
+        },
+    }
+}
+
+fn insert_path(
+    map: &mut serde_json::Map<String, serde_json::Value>,
+    path: &[&str],
+    value: serde_json::Value,
+) {
+    match path {
+        [] => {}
+        [key] => {
+            map.insert(key.to_string(), value);
+        }
+        [parent, rest @ ..] => {
+            let child = map
+                .entry(parent.to_string())
+                .or_insert_with(|| serde_json::Map::new().into());
+            if let serde_json::Value::Object(child) = child {
+                insert_path(child, rest, value);
+            }
+        }
+    }
+}
+
+/// Infer the type of an undeclared value.
+///
+/// Only `true`/`false` and strict JSON number literals are converted, so that zip codes, phone
+/// numbers or SKUs with leading zeros, signs or spaces, as well as `NaN` or `inf`, stay strings.
+/// Integers too large to be represented exactly stay strings as well.
+fn infer(value: &str) -> serde_json::Value {
+    match value {
+        "true" => return true.into(),
+        "false" => return false.into(),
+        _ => {}
+    }
+
+    if value.trim() == value {
+        if let Ok(number) = serde_json::from_str::<serde_json::Number>(value) {
+            let is_integer_literal = !value.contains(['.', 'e', 'E']);
+            if !is_integer_literal || !number.is_f64() {
+                return number.into();
+            }
+        }
+    }
+
+    value.into()
+}
+
+fn to_int(value: &str) -> Option<serde_json::Value> {
+    let value = value.trim();
+    if let Ok(int) = value.parse::<i64>() {
+        return Some(int.into());
+    }
+    if let Ok(uint) = value.parse::<u64>() {
+        return Some(uint.into());
+    }
+    // accept floats without fractional part, e.g. "12.0"
+    let float = value.parse::<f64>().ok()?;
+    if float.is_finite() && float.fract() == 0.0 && float.abs() < i64::MAX as f64 {
+        return Some((float as i64).into());
+    }
+    None
+}
+
+fn to_float(value: &str) -> Option<serde_json::Value> {
+    let float = value.trim().parse::<f64>().ok()?;
+    serde_json::Number::from_f64(float).map(serde_json::Value::Number)
+}
+
+fn to_bool(value: &str) -> Option<serde_json::Value> {
+    match value.trim().to_lowercase().as_str() {
+        "true" | "1" | "yes" | "on" => Some(true.into()),
+        "false" | "0" | "no" | "off" => Some(false.into()),
+        _ => None,
+    }
+}
+
+/// ISO-8601 dates and date-times are normalized to UTC, Unix timestamps in seconds or
+/// milliseconds are converted to ISO-8601 date-times.
+fn to_date(value: &str) -> Option<serde_json::Value> {
+    let value = value.trim();
+    if let Ok(date_time) = DateTime::parse_from_rfc3339(value) {
+        return Some(
+            date_time
+                .with_timezone(&Utc)
+                .to_rfc3339_opts(SecondsFormat::AutoSi, true)
+                .into(),
+        );
+    }
+    if let Ok(date) = NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        return Some(date.format("%Y-%m-%d").to_string().into());
+    }
+    if !value.is_empty() && value.chars().all(|c| c.is_ascii_digit()) {
+        let timestamp = value.parse::<i64>().ok()?;
+        let date_time = if value.len() > 10 {
+            Utc.timestamp_millis_opt(timestamp).single()?
+        } else {
+            Utc.timestamp_opt(timestamp, 0).single()?
+        };
+        return Some(
+            date_time
+                .to_rfc3339_opts(SecondsFormat::AutoSi, true)
+                .into(),
+        );
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use serde_json::json;
+
+    fn coercion(types: &str) -> Coercion {
+        Coercion::new(&HashMap::from([(
+            "segment_property_types".to_string(),
+            types.to_string(),
+        )]))
+        .unwrap()
+    }
+
+    #[test]
+    fn infer_booleans() {
+        assert_eq!(infer("true"), json!(true));
+        assert_eq!(infer("false"), json!(false));
+        assert_eq!(infer("TRUE"), json!("TRUE"));
+        assert_eq!(infer("yes"), json!("yes"));
+    }
+
+    #[test]
+    fn infer_numbers() {
+        assert_eq!(infer("0"), json!(0));
+        assert_eq!(infer("10"), json!(10));
+        assert_eq!(infer("-42"), json!(-42));
+        assert_eq!(infer("19.99"), json!(19.99));
+        assert_eq!(infer("0.5"), json!(0.5));
+        assert_eq!(infer("-0.5"), json!(-0.5));
+        assert_eq!(infer("1e3"), json!(1000.0));
+        assert_eq!(infer("18446744073709551615"), json!(u64::MAX));
+    }
+
+    #[test]
+    fn infer_keeps_identifiers_as_strings() {
+        assert_eq!(infer("01234"), json!("01234"));
+        assert_eq!(infer("0033612345678"), json!("0033612345678"));
+        assert_eq!(infer("+33612345678"), json!("+33612345678"));
+        assert_eq!(
+            infer("4111111111111111111111"),
+            json!("4111111111111111111111")
+        );
+        assert_eq!(infer("0045-360"), json!("0045-360"));
+        assert_eq!(infer(" 12"), json!(" 12"));
+        assert_eq!(infer("12 "), json!("12 "));
+        assert_eq!(infer(".5"), json!(".5"));
+        assert_eq!(infer("5."), json!("5."));
+        assert_eq!(infer("0x1F"), json!("0x1F"));
+        assert_eq!(infer(""), json!(""));
+    }
+
+    #[test]
+    fn infer_never_panics_on_special_floats() {
+        assert_eq!(infer("NaN"), json!("NaN"));
+        assert_eq!(infer("nan"), json!("nan"));
+        assert_eq!(infer("inf"), json!("inf"));
+        assert_eq!(infer("-inf"), json!("-inf"));
+        assert_eq!(infer("infinity"), json!("infinity"));
+        assert_eq!(infer("1e400"), json!("1e400"));
+    }
+
+    #[test]
+    fn declared_string() {
+        let coercion = coercion("zip:string");
+        assert_eq!(coercion.coerce("zip", "75001"), json!("75001"));
+        assert_eq!(coercion.coerce("zip", "true"), json!("true"));
+        assert_eq!(coercion.coerce("other", "75001"), json!(75001));
+    }
+
+    #[test]
+    fn declared_int() {
+        let coercion = coercion("age:int");
+        assert_eq!(coercion.coerce("age", "42"), json!(42));
+        assert_eq!(coercion.coerce("age", "042"), json!(42));
+        assert_eq!(coercion.coerce("age", " 42 "), json!(42));
+        assert_eq!(coercion.coerce("age", "42.0"), json!(42));
+        assert_eq!(coercion.coerce("age", "42.5"), json!("42.5"));
+        assert_eq!(coercion.coerce("age", "NaN"), json!("NaN"));
+        assert_eq!(coercion.coerce("age", "1e400"), json!("1e400"));
+        assert_eq!(coercion.coerce("age", "forty"), json!("forty"));
+    }
+
+    #[test]
+    fn declared_float() {
+        let coercion = coercion("price:float");
+        assert_eq!(coercion.coerce("price", "19.99"), json!(19.99));
+        assert_eq!(coercion.coerce("price", "20"), json!(20.0));
+        assert_eq!(coercion.coerce("price", "NaN"), json!("NaN"));
+        assert_eq!(coercion.coerce("price", "inf"), json!("inf"));
+        assert_eq!(coercion.coerce("price", "-infinity"), json!("-infinity"));
+        assert_eq!(coercion.coerce("price", "free"), json!("free"));
+    }
+
+    #[test]
+    fn declared_bool() {
+        let coercion = coercion("newsletter:bool");
+        assert_eq!(coercion.coerce("newsletter", "TRUE"), json!(true));
+        assert_eq!(coercion.coerce("newsletter", "1"), json!(true));
+        assert_eq!(coercion.coerce("newsletter", "yes"), json!(true));
+        assert_eq!(coercion.coerce("newsletter", "off"), json!(false));
+        assert_eq!(coercion.coerce("newsletter", "0"), json!(false));
+        assert_eq!(coercion.coerce("newsletter", "maybe"), json!("maybe"));
+    }
+
+    #[test]
+    fn declared_json() {
+        let coercion = coercion("meta:json");
+        assert_eq!(
+            coercion.coerce("meta", r#"{"a": [1, "b"]}"#),
+            json!({"a": [1, "b"]})
+        );
+        assert_eq!(coercion.coerce("meta", "[1,2]"), json!([1, 2]));
+        assert_eq!(coercion.coerce("meta", "{broken"), json!("{broken"));
+    }
+
+    #[test]
+    fn declared_date() {
+        let coercion = coercion("birthday:date");
+        assert_eq!(
+            coercion.coerce("birthday", "2024-03-01T10:00:00+02:00"),
+            json!("2024-03-01T08:00:00Z")
+        );
+        assert_eq!(
+            coercion.coerce("birthday", "1990-12-31"),
+            json!("1990-12-31")
+        );
+        assert_eq!(
+            coercion.coerce("birthday", "1700000000"),
+            json!("2023-11-14T22:13:20Z")
+        );
+        assert_eq!(
+            coercion.coerce("birthday", "1700000000123"),
+            json!("2023-11-14T22:13:20.123Z")
+        );
+        assert_eq!(
+            coercion.coerce("birthday", "1990-02-30"),
+            json!("1990-02-30")
+        );
+        assert_eq!(
+            coercion.coerce("birthday", "99999999999999999999"),
+            json!("99999999999999999999")
+        );
+        assert_eq!(coercion.coerce("birthday", "yesterday"), json!("yesterday"));
+    }
+
+    #[test]
+    fn parse_json_values() {
+        let coercion = Coercion::new(&HashMap::from([
+            (
+                "segment_parse_json_properties".to_string(),
+                "true".to_string(),
+            ),
+            (
+                "segment_property_types".to_string(),
+                "raw:string".to_string(),
+            ),
+        ]))
+        .unwrap();
+        assert_eq!(
+            coercion.coerce("address", r#"{"city": "Paris", "zip": "75001"}"#),
+            json!({"city": "Paris", "zip": "75001"})
+        );
+        assert_eq!(coercion.coerce("tags", r#"["a", 1]"#), json!(["a", 1]));
+        assert_eq!(coercion.coerce("raw", "[1]"), json!("[1]"));
+        assert_eq!(coercion.coerce("broken", "{oops"), json!("{oops"));
+        assert_eq!(
+            coercion.coerce("string", r#""quoted""#),
+            json!(r#""quoted""#)
+        );
+        assert_eq!(coercion.coerce("number", "12"), json!(12));
+
+        let disabled = Coercion::default();
+        assert_eq!(disabled.coerce("tags", r#"["a", 1]"#), json!(r#"["a", 1]"#));
+    }
+
+    #[test]
+    fn unflatten_dotted_keys() {
+        let coercion = Coercion::new(&HashMap::from([(
+            "segment_unflatten_properties".to_string(),
+            "true".to_string(),
+        )]))
+        .unwrap();
+        let properties = HashMap::from([
+            ("address.city".to_string(), json!("Paris")),
+            ("address.geo.lat".to_string(), json!(48.85)),
+            ("plan".to_string(), json!("pro")),
+            ("plan.name".to_string(), json!("Pro")),
+            ("user".to_string(), json!({"name": "Jane", "age": 30})),
+            ("user.age".to_string(), json!(31)),
+            ("a..b".to_string(), json!(1)),
+            (".hidden".to_string(), json!(2)),
+        ]);
+        let unflattened = coercion.unflatten(properties);
+
+        let unflattened: serde_json::Map<String, serde_json::Value> =
+            unflattened.into_iter().collect();
+        assert_eq!(
+            serde_json::Value::Object(unflattened),
+            json!({
+                "address": {"city": "Paris", "geo": {"lat": 48.85}},
+                "plan": "pro",
+                "plan.name": "Pro",
+                "user": {"name": "Jane", "age": 31},
+                "a..b": 1,
+                ".hidden": 2
+            })
+        );
+    }
+
+    #[test]
+    fn unflatten_disabled() {
+        let properties = HashMap::from([("address.city".to_string(), json!("Paris"))]);
+        assert_eq!(
+            Coercion::default().unflatten(properties.clone()),
+            properties
+        );
+    }
+
+    #[test]
+    fn declarations_are_validated() {
+        let settings = |types: &str| {
+            HashMap::from([("segment_property_types".to_string(), types.to_string())])
+        };
+        assert_eq!(
+            Coercion::new(&settings("zip:string, age:int,")).is_ok(),
+            true
+        );
+        assert_eq!(Coercion::new(&settings("zip")).is_err(), true);
+        assert_eq!(Coercion::new(&settings("zip:number")).is_err(), true);
+    }
+}
@@ -0,0 +1,136 @@
+use std::collections::HashMap;
+
+use crate::segment_payload::setting_choice;
+
+/// A GA4 recommended event and its Segment spec counterpart, with the properties to rename.
+struct PresetEvent {
+    name: &'static str,
+    segment_name: &'static str,
+    properties: &'static [(&'static str, &'static str)],
+}
+
+// see https://developers.google.com/analytics/devguides/collection/ga4/reference/events
+// and https://segment.com/docs/connections/spec/ecommerce/v2/
+const GA4_PRESET: &[PresetEvent] = &[
+    PresetEvent {
+        name: "purchase",
+        segment_name: "Order Completed",
+        properties: &[("transaction_id", "order_id"), ("value", "revenue")],
+    },
+    PresetEvent {
+        name: "refund",
+        segment_name: "Order Refunded",
+        properties: &[("transaction_id", "order_id"), ("value", "total")],
+    },
+    PresetEvent {
+        name: "begin_checkout",
+        segment_name: "Checkout Started",
+        properties: &[("transaction_id", "order_id")],
+    },
+    PresetEvent {
+        name: "add_payment_info",
+        segment_name: "Payment Info Entered",
+        properties: &[("transaction_id", "order_id")],
+    },
+    PresetEvent {
+        name: "add_to_cart",
+        segment_name: "Product Added",
+        properties: &[],
+    },
+    PresetEvent {
+        name: "remove_from_cart",
+        segment_name: "Product Removed",
+        properties: &[],
+    },
+    PresetEvent {
+        name: "view_cart",
+        segment_name: "Cart Viewed",
+        properties: &[],
+    },
+    PresetEvent {
+        name: "view_item",
+        segment_name: "Product Viewed",
+        properties: &[],
+    },
+    PresetEvent {
+        name: "view_item_list",
+        segment_name: "Product List Viewed",
+        properties: &[("item_list_id", "list_id")],
+    },
+    PresetEvent {
+        name: "select_item",
+        segment_name: "Product Clicked",
+        properties: &[],
+    },
+    PresetEvent {
+        name: "add_to_wishlist",
+        segment_name: "Product Added to Wishlist",
+        properties: &[],
+    },
+    PresetEvent {
+        name: "view_promotion",
+        segment_name: "Promotion Viewed",
+        properties: &[("promotion_name", "name")],
+    },
+    PresetEvent {
+        name: "select_promotion",
+        segment_name: "Promotion Clicked",
+        properties: &[("promotion_name", "name")],
+    },
+    PresetEvent {
+        name: "search",
+        segment_name: "Products Searched",
+        properties: &[("search_term", "query")],
+    },
+    PresetEvent {
+        name: "share",
+        segment_name: "Product Shared",
+        properties: &[("method", "share_via")],
+    },
+    PresetEvent {
+        name: "sign_up",
+        segment_name: "Signed Up",
+        properties: &[],
+    },
+    PresetEvent {
+        name: "login",
+        segment_name: "Signed In",
+        properties: &[],
+    },
+];
+
+/// Map a track event name to its Segment name, reshaping the well-known properties.
+///
+/// The `segment_event_name_mapping` table (`source:Target` pairs separated by commas) takes
+/// precedence over the `segment_event_name_preset` preset, and only renames the event.
+pub(crate) fn map_event(
+    name: &str,
+    properties: &mut HashMap<String, serde_json::Value>,
+    settings: &HashMap<String, String>,
+) -> String {
+    if let Some(mapping) = settings.get("segment_event_name_mapping") {
+        let renamed = mapping.split(',').find_map(|pair| {
+            let (source, target) = pair.split_once(':')?;
+            (source.trim() == name && !target.trim().is_empty()).then_some(target.trim())
+        });
+        if let Some(renamed) = renamed {
+            return renamed.to_string();
+        }
+    }
+
+    if setting_choice(settings, "segment_event_name_preset") == "ga4" {
+        if let Some(event) = GA4_PRESET.iter().find(|event| event.name == name) {
+            for (from, to) in event.properties.iter() {
+                if properties.contains_key(*to) {
+                    continue;
+                }
+                if let Some(value) = properties.remove(*from) {
+                    properties.insert(to.to_string(), value);
+                }
+            }
+            return event.segment_name.to_string();
+        }
+    }
+
+    name.to_string()
+}
@@ -1,14 +1,22 @@
+mod batch;
+mod coercion;
+mod event_mapping;
+mod privacy;
+mod scrubbing;
 mod segment_payload;
+mod user_agent;
 
 use base64::{
     alphabet::STANDARD,
     engine::{general_purpose::PAD, GeneralPurpose},
     Engine,
 };
+use batch::SegmentBatch;
+use coercion::Coercion;
 use exports::edgee::components::data_collection::{
-    Data, Dict, EdgeeRequest, Event, Guest, HttpMethod,
+    Consent, Data, Dict, EdgeeRequest, Event, Guest, HttpMethod, PageData,
 };
-use segment_payload::SegmentPayload;
+use segment_payload::{api_host, seen_at, setting_choice, setting_enabled, SegmentPayload};
 use std::collections::HashMap;
 
 wit_bindgen::generate!({world: "data-collection", path: ".edgee/wit", generate_all});
@@ -16,13 +24,37 @@ export!(SegmentComponent);
 
 struct SegmentComponent;
 
+/// Error returned instead of a request when `segment_drop_on_denied` is enabled and the visitor
+/// denied their consent, so that the Edgee host can tell an intentionally skipped event apart
+/// from a failure.
+pub const SKIPPED_CONSENT_DENIED: &str = "Segment event skipped: consent denied";
+
+/// Refuse to build any Segment message for a visitor who denied their consent, when
+/// `segment_drop_on_denied` is enabled.
+fn check_consent(edgee_event: &Event, cred: &HashMap<String, String>) -> Result<(), String> {
+    if setting_enabled(cred, "segment_drop_on_denied")
+        && edgee_event.consent == Some(Consent::Denied)
+    {
+        return Err(SKIPPED_CONSENT_DENIED.to_string());
+    }
+    Ok(())
+}
+
 impl Guest for SegmentComponent {
     fn page(edgee_event: Event, settings: Dict) -> Result<EdgeeRequest, String> {
+        let cred: HashMap<String, String> = settings
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+        check_consent(&edgee_event, &cred)?;
+
         // create a new segment payload
-        let mut segment_payload = SegmentPayload::new(&edgee_event, &settings, "page".to_string())
+        let mut segment_payload = SegmentPayload::new(&edgee_event, &cred, "page".to_string())
             .map_err(|e| e.to_string())?;
 
         if let Data::Page(ref data) = edgee_event.data {
+            let coercion = Coercion::new(&cred).map_err(|e| e.to_string())?;
+
             // page event properties
             let mut properties = HashMap::new();
 
@@ -53,18 +85,40 @@ impl Guest for SegmentComponent {
 
             // iterate over page.properties and add them to properties
             for (key, value) in data.properties.clone().iter() {
-                properties.insert(key.clone(), parse_value(value));
+                properties.insert(key.clone(), coercion.coerce(key, value));
             }
 
+            let mut properties = coercion.unflatten(properties);
+            if !segment_payload.is_anonymized() {
+                insert_session_properties(&mut properties, &edgee_event, &cred);
+            }
             segment_payload.properties = Some(properties);
 
-            Ok(build_edgee_request(segment_payload, &settings))
+            // page name and category
+            segment_payload.name = page_name(data, &cred);
+            if !data.category.is_empty() {
+                segment_payload.category = Some(data.category.clone());
+            }
+
+            let lifecycle_events = build_lifecycle_payloads(&segment_payload, &edgee_event, &cred);
+            let mut batch = SegmentBatch::new(segment_payload);
+            for lifecycle_event in lifecycle_events {
+                batch.push_derived(lifecycle_event);
+            }
+
+            build_edgee_request(batch, &cred)
         } else {
             Err("Missing page data".to_string())
         }
     }
 
     fn track(edgee_event: Event, settings: Dict) -> Result<EdgeeRequest, String> {
+        let cred: HashMap<String, String> = settings
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+        check_consent(&edgee_event, &cred)?;
+
         if let Data::Track(ref data) = edgee_event.data {
             // check if edgee_payload.track is empty
             if data.name.is_empty() {
@@ -72,28 +126,53 @@ impl Guest for SegmentComponent {
             }
 
             // create a new segment payload
-            let mut segment_payload =
-                SegmentPayload::new(&edgee_event, &settings, "track".to_string())
-                    .map_err(|e| e.to_string())?;
+            let mut segment_payload = SegmentPayload::new(&edgee_event, &cred, "track".to_string())
+                .map_err(|e| e.to_string())?;
 
-            // event name and properties
-            segment_payload.event = Option::from(data.name.clone());
+            let coercion = Coercion::new(&cred).map_err(|e| e.to_string())?;
 
+            // event properties and name
             let mut properties = HashMap::new();
 
             // iterate over page.properties and add them to properties
             for (key, value) in data.properties.clone().iter() {
-                properties.insert(key.clone(), parse_value(value));
+                properties.insert(key.clone(), coercion.coerce(key, value));
+            }
+            let mut properties = coercion.unflatten(properties);
+            if !segment_payload.is_anonymized() {
+                insert_session_properties(&mut properties, &edgee_event, &cred);
+            }
+
+            // e-commerce products, following the Segment E-commerce v2 spec
+            if !data.products.is_empty() {
+                let products = build_products(&data.products, &mut properties, &coercion);
+                properties.insert("products".to_string(), products.into());
             }
+
+            // rename the event (and its well-known properties) to the Segment spec
+            segment_payload.event =
+                Some(event_mapping::map_event(&data.name, &mut properties, &cred));
             segment_payload.properties = Some(properties);
 
-            Ok(build_edgee_request(segment_payload, &settings))
+            let group = build_group_payload(&segment_payload, &cred, &data.properties, &coercion);
+            let mut batch = SegmentBatch::new(segment_payload);
+            if let Some(group) = group {
+                batch.push_derived(group);
+            }
+
+            build_edgee_request(batch, &cred)
         } else {
             Err("Missing track data".to_string())
         }
     }
 
     fn user(edgee_event: Event, settings: Dict) -> Result<EdgeeRequest, String> {
+        let cred: HashMap<String, String> = settings
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+        check_consent(&edgee_event, &cred)?;
+
         if let Data::User(ref data) = edgee_event.data {
             // check if edgee_payload.identify is empty
             if data.user_id.is_empty() && data.anonymous_id.is_empty() {
@@ -102,49 +181,297 @@ impl Guest for SegmentComponent {
 
             // Convert edgee_payload to segment Payload
             let mut segment_payload =
-                SegmentPayload::new(&edgee_event, &settings, "identify".to_string())
+                SegmentPayload::new(&edgee_event, &cred, "identify".to_string())
                     .map_err(|e| e.to_string())?;
+            segment_payload.set_user_ids(data);
+
+            let coercion = Coercion::new(&cred).map_err(|e| e.to_string())?;
 
             // get edgee_payload.identify.properties and set segment_payload.traits with it
             let mut properties = HashMap::new();
 
             // iterate over page.properties and add them to properties
             for (key, value) in data.properties.clone().iter() {
-                properties.insert(key.clone(), parse_value(value));
+                properties.insert(key.clone(), coercion.coerce(key, value));
+            }
+            privacy::hash_traits(&mut properties, &cred);
+            segment_payload.traits = Some(coercion.unflatten(properties));
+
+            // alias the anonymous visitor to the identified user, either instead of the
+            // identify call (`alias` mode) or along with it (`batch` mode)
+            let alias_mode = setting_choice(&cred, "segment_send_alias");
+            let send_alias = alias_mode != "none"
+                && !data.user_id.is_empty()
+                && !data.anonymous_id.is_empty()
+                && data.user_id != data.anonymous_id
+                && !segment_payload.is_anonymized();
+            let group = build_group_payload(&segment_payload, &cred, &data.properties, &coercion);
+            let mut batch = SegmentBatch::default();
+            if send_alias {
+                let mut alias_payload = segment_payload.derive("alias", "alias");
+                alias_payload.user_id = Some(data.user_id.clone());
+                alias_payload.anonymous_id = None;
+                alias_payload.previous_id = Some(data.anonymous_id.clone());
+                batch.push(alias_payload);
+            }
+            if !send_alias || alias_mode != "alias" {
+                batch.push(segment_payload);
+            }
+
+            if let Some(group) = group {
+                batch.push_derived(group);
             }
-            segment_payload.traits = Some(properties);
 
-            Ok(build_edgee_request(segment_payload, &settings))
+            build_edgee_request(batch, &cred)
         } else {
             Err("Missing user data".to_string())
         }
     }
 }
 
-fn parse_value(value: &str) -> serde_json::Value {
-    if value == "true" {
-        serde_json::Value::from(true)
-    } else if value == "false" {
-        serde_json::Value::from(false)
-    } else if value.parse::<f64>().is_ok() {
-        serde_json::Value::Number(value.parse().unwrap())
-    } else {
-        serde_json::Value::String(value.to_string())
+/// Name of the page: the name set on the page call, or else the first matching rule of
+/// `segment_page_name_rules` (`/path-prefix:Name` pairs separated by commas), or else the
+/// title or path according to `segment_page_name_fallback`.
+fn page_name(data: &PageData, cred: &HashMap<String, String>) -> Option<String> {
+    if !data.name.is_empty() {
+        return Some(data.name.clone());
+    }
+
+    if let Some(rules) = cred.get("segment_page_name_rules") {
+        let name = rules.split(',').find_map(|rule| {
+            let (prefix, name) = rule.split_once(':')?;
+            (!prefix.trim().is_empty()
+                && data.path.starts_with(prefix.trim())
+                && !name.trim().is_empty())
+            .then_some(name.trim())
+        });
+        if let Some(name) = name {
+            return Some(name.to_string());
+        }
+    }
+
+    let fallback = match setting_choice(cred, "segment_page_name_fallback") {
+        "title" => &data.title,
+        "path" => &data.path,
+        _ => return None,
+    };
+    (!fallback.is_empty()).then(|| fallback.clone())
+}
+
+/// Build the synthetic lifecycle tracks derived from a page call, when enabled:
+/// - "Session Started" (`segment_session_started_event`) on the first page of a session,
+/// - "First Visit" (`segment_first_visit_event`) on the first page of the first session.
+///
+/// Their names can be changed with the `_name` suffixed settings.
+fn build_lifecycle_payloads(
+    page_payload: &SegmentPayload,
+    edgee_event: &Event,
+    cred: &HashMap<String, String>,
+) -> Vec<SegmentPayload> {
+    let session = &edgee_event.context.session;
+    if !session.session_start {
+        return vec![];
+    }
+
+    let event_name = |setting: &str, default: &str| {
+        cred.get(&format!("{setting}_name"))
+            .filter(|name| !name.is_empty())
+            .cloned()
+            .unwrap_or_else(|| default.to_string())
+    };
+
+    let mut lifecycle_events = vec![];
+    if setting_enabled(cred, "segment_session_started_event") {
+        let mut properties = HashMap::new();
+        if !session.session_id.is_empty() {
+            properties.insert("session_id".to_string(), session.session_id.clone().into());
+        }
+        if !session.previous_session_id.is_empty() {
+            properties.insert(
+                "previous_session_id".to_string(),
+                session.previous_session_id.clone().into(),
+            );
+        }
+        properties.insert("session_count".to_string(), session.session_count.into());
+        lifecycle_events.push((
+            "session_started",
+            event_name("segment_session_started_event", "Session Started"),
+            properties,
+        ));
+    }
+    if setting_enabled(cred, "segment_first_visit_event") && session.session_count == 1 {
+        let mut properties = HashMap::new();
+        if let Some(first_seen) = seen_at(session.first_seen) {
+            properties.insert("first_seen".to_string(), serde_json::json!(first_seen));
+        }
+        lifecycle_events.push((
+            "first_visit",
+            event_name("segment_first_visit_event", "First Visit"),
+            properties,
+        ));
+    }
+
+    lifecycle_events
+        .into_iter()
+        .map(|(suffix, name, properties)| {
+            let mut segment_payload = page_payload.derive("track", suffix);
+            segment_payload.event = Some(name);
+            // the session properties of anonymized visitors are not sent, see `anonymize`
+            if !page_payload.is_anonymized() {
+                segment_payload.properties = Some(properties);
+            }
+            segment_payload
+        })
+        .collect()
+}
+
+/// Add the Edgee session (id, count, previous id, start flag, first and last seen) to the event
+/// properties, when `segment_session_id_location` is `properties`. Properties set by the event
+/// are kept.
+fn insert_session_properties(
+    properties: &mut HashMap<String, serde_json::Value>,
+    edgee_event: &Event,
+    settings: &HashMap<String, String>,
+) {
+    let in_properties = setting_choice(settings, "segment_session_id_location") == "properties";
+    let session = &edgee_event.context.session;
+    if !in_properties || session.session_id.is_empty() {
+        return;
+    }
+
+    properties
+        .entry("session_id".to_string())
+        .or_insert_with(|| session.session_id.clone().into());
+    if session.session_count > 0 {
+        properties
+            .entry("session_count".to_string())
+            .or_insert_with(|| session.session_count.into());
+    }
+    if !session.previous_session_id.is_empty() {
+        properties
+            .entry("previous_session_id".to_string())
+            .or_insert_with(|| session.previous_session_id.clone().into());
+    }
+    properties
+        .entry("session_start".to_string())
+        .or_insert_with(|| session.session_start.into());
+    for (key, seen) in [
+        ("first_seen", session.first_seen),
+        ("last_seen", session.last_seen),
+    ] {
+        if let Some(seen) = seen_at(seen) {
+            properties
+                .entry(key.to_string())
+                .or_insert_with(|| serde_json::json!(seen));
+        }
+    }
+}
+
+/// Convert Edgee products into Segment E-commerce v2 `products[]` objects.
+///
+/// Line-item fields (including a product `revenue` or `total`) stay on their product; only the
+/// `currency` shared by all the products is lifted into the event properties, unless the event
+/// already defines it.
+fn build_products(
+    products: &[Dict],
+    properties: &mut HashMap<String, serde_json::Value>,
+    coercion: &Coercion,
+) -> Vec<serde_json::Value> {
+    let mut segment_products = vec![];
+    for product in products.iter() {
+        let mut segment_product = serde_json::Map::new();
+        for (key, value) in product.iter() {
+            if value.is_empty() {
+                continue;
+            }
+            match key.as_str() {
+                // identifiers and labels are kept as strings, e.g. a "0042" sku
+                "product_id" | "id" => {
+                    segment_product.insert("product_id".to_string(), value.clone().into());
+                }
+                "sku" | "name" | "category" | "brand" | "variant" | "coupon" | "url"
+                | "image_url" | "currency" => {
+                    segment_product.insert(key.clone(), value.clone().into());
+                }
+                // price, quantity, position and custom properties
+                _ => {
+                    segment_product.insert(key.clone(), coercion.coerce(key, value));
+                }
+            }
+        }
+        segment_products.push(segment_product);
+    }
+
+    let mut currencies = segment_products
+        .iter()
+        .map(|product| product.get("currency"));
+    if let Some(Some(currency)) = currencies.next() {
+        if currencies.all(|other| other == Some(currency)) {
+            properties
+                .entry("currency".to_string())
+                .or_insert_with(|| currency.clone());
+        }
     }
+
+    segment_products.into_iter().map(Into::into).collect()
 }
 
-fn build_edgee_request(segment_payload: SegmentPayload, settings: &Dict) -> EdgeeRequest {
-    let cred: HashMap<String, String> = settings
+/// Build a Segment `group` call, derived from the event's message, when the given properties
+/// carry the configured group id key.
+///
+/// Group traits are the properties starting with `segment_group_traits_prefix`, prefix removed.
+fn build_group_payload(
+    segment_payload: &SegmentPayload,
+    cred: &HashMap<String, String>,
+    properties: &Dict,
+    coercion: &Coercion,
+) -> Option<SegmentPayload> {
+    let group_id_key = cred
+        .get("segment_group_id_key")
+        .filter(|key| !key.is_empty())?;
+    let group_id = properties
         .iter()
-        .map(|(key, value)| (key.to_string(), value.to_string()))
-        .collect();
+        .find(|(key, value)| key == group_id_key && !value.is_empty())
+        .map(|(_, value)| value.clone())?;
+
+    let mut group_payload = segment_payload.derive("group", "group");
+    group_payload.group_id = Some(group_id);
+
+    if let Some(prefix) = cred
+        .get("segment_group_traits_prefix")
+        .filter(|prefix| !prefix.is_empty())
+    {
+        let traits: HashMap<String, serde_json::Value> = properties
+            .iter()
+            .filter(|(key, _)| key != group_id_key)
+            .filter_map(|(key, value)| {
+                key.strip_prefix(prefix.as_str())
+                    .filter(|name| !name.is_empty())
+                    .map(|name| (name.to_string(), coercion.coerce(key, value)))
+            })
+            .collect();
+        if !traits.is_empty() {
+            group_payload.traits = Some(traits);
+        }
+    }
 
+    Some(group_payload)
+}
+
+/// Build the request sending the messages of the batch to Segment.
+fn build_edgee_request(
+    batch: SegmentBatch,
+    cred: &HashMap<String, String>,
+) -> Result<EdgeeRequest, String> {
     let key = cred
         .get("segment_write_key")
         .unwrap_or(&String::new())
         .to_owned();
     let key = GeneralPurpose::new(&STANDARD, PAD).encode(format!("{key}:"));
 
+    // settings were already validated by SegmentPayload::new
+    let host = api_host(cred).unwrap_or_default();
+
     let mut headers = vec![];
     headers.push((String::from("authorization"), format!("Basic {key}")));
     headers.push((
@@ -152,21 +479,35 @@ fn build_edgee_request(segment_payload: SegmentPayload, settings: &Dict) -> Edge
         String::from("application/json"),
     ));
 
-    EdgeeRequest {
+    // the client headers carry the client IP, so they are only forwarded by default when the
+    // full IP is sent, and never when geolocation is disabled
+    let forward_client_headers = if setting_enabled(cred, "segment_disable_geolocation") {
+        false
+    } else {
+        match cred.get("segment_forward_client_headers") {
+            Some(forward) if !forward.is_empty() => forward.eq_ignore_ascii_case("true"),
+            _ => setting_choice(cred, "segment_ip_mode") == "full" && !batch.is_anonymized(),
+        }
+    };
+
+    let (endpoint, body) = batch.build().map_err(|e| e.to_string())?;
+
+    Ok(EdgeeRequest {
         method: HttpMethod::Post,
-        url: String::from("https://api.segment.io/v1/track"),
+        url: format!("{host}{endpoint}"),
         headers,
-        forward_client_headers: true,
-        body: serde_json::to_string(&segment_payload).unwrap(),
-    }
+        forward_client_headers,
+        body,
+    })
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::exports::edgee::components::data_collection::{
-        Campaign, Client, Context, EventType, PageData, Session, TrackData, UserData,
+        Campaign, Client, Context, EventType, Session, TrackData, UserData,
     };
+    use chrono::{TimeZone, Utc};
     use exports::edgee::components::data_collection::Consent;
     use pretty_assertions::assert_eq;
     use uuid::Uuid;
@@ -511,203 +852,1866 @@ mod tests {
         let edgee_request = result.unwrap();
         assert_eq!(edgee_request.method, HttpMethod::Post);
         assert!(!edgee_request.body.is_empty());
-        assert_eq!(
-            edgee_request.url.starts_with("https://api.segment.io"),
-            true
-        );
+        assert_eq!(edgee_request.url, "https://api.segment.io/v1/page");
         // add more checks (headers, querystring, etc.)
     }
 
     #[test]
-    fn page_without_consent() {
-        let event = sample_page_event(None, "abc".to_string(), "fr".to_string(), true);
-        let settings = sample_settings();
-        let result = SegmentComponent::page(event, settings);
+    fn page_anonymized_without_consent() {
+        let mut settings = sample_settings();
+        settings.push((
+            "segment_anonymize_without_consent".to_string(),
+            "true".to_string(),
+        ));
+        settings.push(("segment_anonymization_salt".to_string(), "salt".to_string()));
+
+        for consent in [Consent::Pending, Consent::Denied] {
+            let mut event =
+                sample_page_event(Some(consent), "abc".to_string(), "fr".to_string(), true);
+            event.context.client.user_agent = "Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/128.0.0.0 Safari/537.36".to_string();
+            let result = SegmentComponent::page(event, settings.clone());
+            let body: serde_json::Value = serde_json::from_str(&result.unwrap().body).unwrap();
+
+            assert_eq!(body.get("userId"), None);
+            assert_eq!(
+                body["anonymousId"],
+                privacy::daily_hash("salt", Utc.timestamp_micros(123).unwrap(), "abc")
+            );
+            assert_eq!(body["context"]["ip"], "192.168.0.0");
+            assert_eq!(body["context"]["user_agent"], "Chrome/128");
+            assert_eq!(body["context"].get("location"), None);
+            assert_eq!(body["context"].get("traits"), None);
+            assert_eq!(body["context"]["userAgentData"].get("model"), None);
+            assert_eq!(body["context"]["device"].get("model"), None);
+            assert!(!body.to_string().contains("\"123\""));
+            assert!(!body.to_string().contains("\"456\""));
+        }
 
-        assert_eq!(result.is_err(), false);
-        let edgee_request = result.unwrap();
-        assert_eq!(edgee_request.method, HttpMethod::Post);
-        assert!(!edgee_request.body.is_empty());
+        // the profile doesn't apply to granted or unknown consent
+        for consent in [Some(Consent::Granted), None] {
+            let event = sample_page_event(consent, "abc".to_string(), "fr".to_string(), true);
+            let result = SegmentComponent::page(event, settings.clone());
+            let body: serde_json::Value = serde_json::from_str(&result.unwrap().body).unwrap();
+            assert_eq!(body["userId"], "123");
+            assert_eq!(body["anonymousId"], "456");
+            assert_eq!(body["context"]["ip"], "192.168.0.1");
+        }
     }
 
     #[test]
-    fn page_with_edgee_id_uuid() {
-        let event = sample_page_event(
-            Some(Consent::Granted),
-            Uuid::new_v4().to_string(),
-            "fr".to_string(),
-            true,
-        );
-        let settings = sample_settings();
-        let result = SegmentComponent::page(event, settings);
-
-        assert_eq!(result.is_err(), false);
-        let edgee_request = result.unwrap();
-        assert_eq!(edgee_request.method, HttpMethod::Post);
-        assert!(!edgee_request.body.is_empty());
+    fn page_anonymized_days_are_not_linked() {
+        for location in ["context", "properties"] {
+            let mut settings = sample_settings();
+            settings.push((
+                "segment_anonymize_without_consent".to_string(),
+                "true".to_string(),
+            ));
+            settings.push((
+                "segment_session_id_location".to_string(),
+                location.to_string(),
+            ));
+            settings.push((
+                "segment_session_started_event".to_string(),
+                "true".to_string(),
+            ));
+            settings.push(("segment_first_visit_event".to_string(), "true".to_string()));
+
+            let mut first_day = sample_page_event(
+                Some(Consent::Pending),
+                "abc".to_string(),
+                "fr".to_string(),
+                true,
+            );
+            first_day.context.session.session_id = "1727431200".to_string();
+            first_day.context.session.previous_session_id = "".to_string();
+            first_day.context.session.session_count = 1;
+            first_day.context.session.first_seen = 1727431200;
+            first_day.context.session.last_seen = 1727431200;
+            let mut next_day = first_day.clone();
+            next_day.uuid = Uuid::new_v4().to_string();
+            next_day.timestamp_micros += 86_400_000_000;
+            next_day.context.session.session_id = "1727517600".to_string();
+            next_day.context.session.previous_session_id = "1727431200".to_string();
+            next_day.context.session.session_count = 2;
+            next_day.context.session.last_seen = 1727517600;
+
+            let first_day = SegmentComponent::page(first_day, settings.clone()).unwrap();
+            let next_day = SegmentComponent::page(next_day, settings).unwrap();
+            let first_day: serde_json::Value = serde_json::from_str(&first_day.body).unwrap();
+            let first_day = &first_day["batch"][0];
+            let next_day = next_day.body;
+
+            let anonymous_id = first_day["anonymousId"].as_str().unwrap();
+            assert!(!next_day.contains(anonymous_id));
+            // neither the previous session id nor the first seen time is sent
+            assert!(!next_day.contains("1727431200"));
+            assert!(!next_day.contains("2024-09-27T10:00:00Z"));
+            assert!(!next_day.contains("sessionId"));
+            assert!(!next_day.contains("session_id"));
+        }
     }
 
     #[test]
-    fn page_with_empty_locale() {
+    fn page_anonymized_drops_ip() {
+        let mut settings = sample_settings();
+        settings.push((
+            "segment_anonymize_without_consent".to_string(),
+            "true".to_string(),
+        ));
+        settings.push(("segment_anonymization_ip".to_string(), "drop".to_string()));
         let event = sample_page_event(
-            Some(Consent::Granted),
-            Uuid::new_v4().to_string(),
-            "".to_string(),
+            Some(Consent::Denied),
+            "abc".to_string(),
+            "fr".to_string(),
             true,
         );
-
-        let settings = sample_settings();
         let result = SegmentComponent::page(event, settings);
+        let body: serde_json::Value = serde_json::from_str(&result.unwrap().body).unwrap();
 
-        assert_eq!(result.is_err(), false);
-        let edgee_request = result.unwrap();
-        assert_eq!(edgee_request.method, HttpMethod::Post);
-        assert!(!edgee_request.body.is_empty());
+        assert_eq!(body["context"]["ip"], "0.0.0.0");
+        // the salt defaults to the write key
+        assert_eq!(
+            body["anonymousId"],
+            privacy::daily_hash("abc", Utc.timestamp_micros(123).unwrap(), "abc")
+        );
+        // unknown user-agents are dropped
+        assert_eq!(body["context"].get("user_agent"), None);
     }
 
     #[test]
-    fn page_without_context_page_data() {
-        let event = sample_page_event_without_context_page_data(
-            Some(Consent::Granted),
-            Uuid::new_v4().to_string(),
-            "".to_string(),
+    fn page_with_invalid_anonymization_ip_fails() {
+        let event = sample_page_event(
+            Some(Consent::Denied),
+            "abc".to_string(),
+            "fr".to_string(),
             true,
         );
-
-        let settings = sample_settings();
+        let mut settings = sample_settings();
+        settings.push(("segment_anonymization_ip".to_string(), "hash".to_string()));
         let result = SegmentComponent::page(event, settings);
 
-        assert_eq!(result.is_err(), false);
-        let edgee_request = result.unwrap();
-        assert_eq!(edgee_request.method, HttpMethod::Post);
-        assert!(!edgee_request.body.is_empty());
+        assert_eq!(result.is_err(), true);
     }
 
     #[test]
-    fn page_without_context_campaign_data() {
-        let event = sample_page_event_without_context_campaign_data(
-            Some(Consent::Granted),
-            Uuid::new_v4().to_string(),
-            "".to_string(),
+    fn user_anonymized_without_consent() {
+        let mut settings = sample_settings();
+        settings.push((
+            "segment_anonymize_without_consent".to_string(),
+            "true".to_string(),
+        ));
+        settings.push(("segment_send_alias".to_string(), "batch".to_string()));
+        let event = sample_user_event(
+            Some(Consent::Pending),
+            "abc".to_string(),
+            "fr".to_string(),
             true,
         );
-
-        let settings = sample_settings();
-        let result = SegmentComponent::page(event, settings);
-
-        assert_eq!(result.is_err(), false);
-        let edgee_request = result.unwrap();
-        assert_eq!(edgee_request.method, HttpMethod::Post);
-        assert!(!edgee_request.body.is_empty());
-    }
-
-    #[test]
-    fn page_not_session_start() {
-        let event = sample_page_event(None, Uuid::new_v4().to_string(), "".to_string(), false);
-        let settings = sample_settings();
-        let result = SegmentComponent::page(event, settings);
-
-        assert_eq!(result.is_err(), false);
+        let result = SegmentComponent::user(event, settings);
         let edgee_request = result.unwrap();
-        assert_eq!(edgee_request.method, HttpMethod::Post);
-        assert!(!edgee_request.body.is_empty());
-    }
+        let body: serde_json::Value = serde_json::from_str(&edgee_request.body).unwrap();
 
-    #[test]
-    fn page_without_project_id_fails() {
-        let event = sample_page_event(None, "abc".to_string(), "fr".to_string(), true);
-        let settings: Vec<(String, String)> = vec![]; // empty
-        let result = SegmentComponent::page(event, settings); // this should panic!
-        assert_eq!(result.is_err(), true);
+        // no alias, and no user id nor traits on the identify call
+        assert_eq!(edgee_request.url.ends_with("/v1/identify"), true);
+        assert_eq!(body.get("userId"), None);
+        assert_eq!(body.get("traits"), None);
+        assert_eq!(body["anonymousId"].as_str().unwrap().len(), 64);
     }
 
     #[test]
-    fn page_without_write_key_fails() {
-        let event = sample_page_event(None, "abc".to_string(), "fr".to_string(), true);
-        let settings: Vec<(String, String)> = vec![
-            ("segment_project_id".to_string(), "abc".to_string()), // only project ID
+    fn denied_consent_is_dropped() {
+        let mut settings = sample_settings();
+        settings.push(("segment_drop_on_denied".to_string(), "true".to_string()));
+
+        let results = [
+            SegmentComponent::page(
+                sample_page_event(
+                    Some(Consent::Denied),
+                    "abc".to_string(),
+                    "fr".to_string(),
+                    true,
+                ),
+                settings.clone(),
+            ),
+            SegmentComponent::track(
+                sample_track_event(
+                    "event-name".to_string(),
+                    Some(Consent::Denied),
+                    "abc".to_string(),
+                    "fr".to_string(),
+                    true,
+                ),
+                settings.clone(),
+            ),
+            SegmentComponent::user(
+                sample_user_event(
+                    Some(Consent::Denied),
+                    "abc".to_string(),
+                    "fr".to_string(),
+                    true,
+                ),
+                settings.clone(),
+            ),
         ];
-        let result = SegmentComponent::page(event, settings); // this should panic!
-        assert_eq!(result.is_err(), true);
+        for result in results {
+            // nothing but the sentinel leaves the component
+            assert_eq!(result.err(), Some(SKIPPED_CONSENT_DENIED.to_string()));
+        }
+        assert!(!SKIPPED_CONSENT_DENIED.contains("abc"));
+        assert!(!SKIPPED_CONSENT_DENIED.contains("192.168.0.1"));
     }
 
     #[test]
-    fn track_with_consent() {
-        let event = sample_track_event(
-            "event-name".to_string(),
-            Some(Consent::Granted),
+    fn denied_consent_is_sent_without_drop_on_denied() {
+        let event = sample_page_event(
+            Some(Consent::Denied),
             "abc".to_string(),
             "fr".to_string(),
             true,
         );
-        let settings = sample_settings();
-        let result = SegmentComponent::track(event, settings);
-        assert_eq!(result.clone().is_err(), false);
-        let edgee_request = result.unwrap();
-        assert_eq!(edgee_request.method, HttpMethod::Post);
-        assert!(!edgee_request.body.is_empty());
+        let result = SegmentComponent::page(event, sample_settings());
+        assert_eq!(result.is_ok(), true);
+
+        let mut settings = sample_settings();
+        settings.push(("segment_drop_on_denied".to_string(), "true".to_string()));
+        for consent in [Some(Consent::Granted), Some(Consent::Pending), None] {
+            let event = sample_page_event(consent, "abc".to_string(), "fr".to_string(), true);
+            let result = SegmentComponent::page(event, settings.clone());
+            assert_eq!(result.is_ok(), true);
+        }
     }
 
     #[test]
-    fn track_with_empty_name_fails() {
-        let event = sample_track_event(
-            "".to_string(),
+    fn page_with_pii_scrubbing() {
+        let mut settings = sample_settings();
+        settings.push(("segment_scrub_pii".to_string(), "true".to_string()));
+        settings.push((
+            "segment_scrub_query_params".to_string(),
+            "reset_token,utm_term".to_string(),
+        ));
+        let mut event = sample_page_event(
             Some(Consent::Granted),
             "abc".to_string(),
             "fr".to_string(),
             true,
         );
-        let settings = sample_settings();
-        let result = SegmentComponent::track(event, settings);
-        assert_eq!(result.is_err(), true);
+        if let Data::Page(ref mut data) = event.data {
+            data.url =
+                "https://example.com/reset?reset_token=abc&email=john%40example.com".to_string();
+            data.search = "?reset_token=abc&email=john%40example.com".to_string();
+            data.referrer = "https://mail.example.com/?to=john@example.com".to_string();
+            data.properties
+                .push(("phone".to_string(), "+33 6 12 34 56 78".to_string()));
+            data.properties
+                .push(("card".to_string(), "4111111111111111".to_string()));
+            data.properties
+                .push(("order_id".to_string(), "33612345678".to_string()));
+        }
+        event
+            .context
+            .user
+            .properties
+            .push(("email".to_string(), "john@example.com".to_string()));
+        event.context.campaign.content = "john@example.com".to_string();
+
+        let result = SegmentComponent::page(event, settings);
+        let body: serde_json::Value = serde_json::from_str(&result.unwrap().body).unwrap();
+
+        assert_eq!(
+            body["properties"]["url"],
+            "https://example.com/reset?reset_token=[REDACTED]&email=[REDACTED]"
+        );
+        assert_eq!(
+            body["context"]["page"]["search"],
+            "?reset_token=[REDACTED]&email=[REDACTED]"
+        );
+        assert_eq!(
+            body["context"]["page"]["referrer"],
+            "https://mail.example.com/?to=[REDACTED]"
+        );
+        assert_eq!(body["properties"]["phone"], "[REDACTED]");
+        assert_eq!(body["properties"]["card"], "[REDACTED]");
+        assert_eq!(body["properties"]["order_id"], 33612345678_u64);
+        assert_eq!(body["context"]["campaign"]["term"], "[REDACTED]");
+        assert_eq!(body["context"]["campaign"]["content"], "[REDACTED]");
+        assert_eq!(body["context"]["campaign"]["source"], "random");
+        assert_eq!(body["context"]["traits"]["email"], "[REDACTED]");
+        assert_eq!(body["properties"]["prop1"], false);
+        assert!(!body.to_string().contains("john"));
     }
 
     #[test]
-    fn user_event() {
-        let event = sample_user_event(
+    fn page_with_pii_scrubbing_and_path_name() {
+        let mut settings = sample_settings();
+        settings.push(("segment_scrub_pii".to_string(), "true".to_string()));
+        settings.push(("segment_page_name_fallback".to_string(), "path".to_string()));
+        let mut event = sample_page_event(None, "abc".to_string(), "fr".to_string(), true);
+        if let Data::Page(ref mut data) = event.data {
+            data.name = "".to_string();
+            data.category = "john@example.com".to_string();
+            data.path = "/users/john@example.com".to_string();
+            data.url = "https://example.com/users/john@example.com".to_string();
+        }
+
+        let result = SegmentComponent::page(event, settings);
+        let body: serde_json::Value = serde_json::from_str(&result.unwrap().body).unwrap();
+
+        assert_eq!(body["name"], "/users/[REDACTED]");
+        assert_eq!(body["category"], "[REDACTED]");
+        assert_eq!(body["context"]["page"]["path"], "/users/[REDACTED]");
+        assert!(!body.to_string().contains("john"));
+    }
+
+    #[test]
+    fn user_with_pii_scrubbing() {
+        let mut settings = sample_settings();
+        settings.push(("segment_scrub_pii".to_string(), "true".to_string()));
+        settings.push(("segment_redaction_marker".to_string(), "***".to_string()));
+        let mut event = sample_user_event(
             Some(Consent::Granted),
             "abc".to_string(),
             "fr".to_string(),
             true,
         );
-        let settings = sample_settings();
+        if let Data::User(ref mut data) = event.data {
+            data.properties
+                .push(("email".to_string(), "john@example.com".to_string()));
+        }
         let result = SegmentComponent::user(event, settings);
+        let body: serde_json::Value = serde_json::from_str(&result.unwrap().body).unwrap();
 
-        assert_eq!(result.clone().is_err(), false);
+        assert_eq!(body["traits"]["email"], "***");
+        assert_eq!(body["userId"], "123");
     }
 
     #[test]
-    fn user_event_without_anonymous_id() {
-        let event =
-            sample_user_event_without_anonymous_id(Some(Consent::Granted), "fr".to_string(), true);
-        let settings = sample_settings();
+    fn user_with_hashed_traits() {
+        let mut settings = sample_settings();
+        settings.push((
+            "segment_hashed_traits".to_string(),
+            "email,phone".to_string(),
+        ));
+        settings.push((
+            "segment_hashed_traits_clear_suffix".to_string(),
+            "_clear".to_string(),
+        ));
+        let mut event = sample_user_event(
+            Some(Consent::Granted),
+            "abc".to_string(),
+            "fr".to_string(),
+            true,
+        );
+        if let Data::User(ref mut data) = event.data {
+            data.properties
+                .push(("email".to_string(), "John@Example.com".to_string()));
+            data.properties
+                .push(("phone".to_string(), "+1 (555) 123-4567".to_string()));
+        }
         let result = SegmentComponent::user(event, settings);
+        let body: serde_json::Value = serde_json::from_str(&result.unwrap().body).unwrap();
 
-        assert_eq!(result.clone().is_err(), false);
+        assert_eq!(
+            body["traits"]["email"],
+            segment_payload::sha256_hex("john@example.com")
+        );
+        assert_eq!(body["traits"]["email_clear"], "John@Example.com");
+        assert_eq!(
+            body["traits"]["phone"],
+            segment_payload::sha256_hex("+15551234567")
+        );
+        assert_eq!(body["traits"]["prop1"], "value1");
     }
 
     #[test]
-    fn user_event_without_ids_fails() {
-        let event = sample_user_event_without_ids(Some(Consent::Granted), "fr".to_string(), true);
-        let settings = sample_settings();
-        let result = SegmentComponent::user(event, settings);
+    fn page_with_hashed_context_traits() {
+        let mut settings = sample_settings();
+        settings.push(("segment_hashed_traits".to_string(), "email".to_string()));
+        let mut event = sample_page_event(
+            Some(Consent::Granted),
+            "abc".to_string(),
+            "fr".to_string(),
+            true,
+        );
+        event
+            .context
+            .user
+            .properties
+            .push(("email".to_string(), "john@example.com".to_string()));
+        let result = SegmentComponent::page(event, settings);
+        let body: serde_json::Value = serde_json::from_str(&result.unwrap().body).unwrap();
 
-        assert_eq!(result.clone().is_err(), true);
         assert_eq!(
-            result
-                .clone()
-                .err()
-                .unwrap()
-                .to_string()
-                .contains("is not set"),
-            true
+            body["context"]["traits"]["email"],
+            segment_payload::sha256_hex("john@example.com")
         );
+        assert_eq!(body["context"]["traits"].get("email_clear"), None);
     }
 
     #[test]
-    fn track_event_without_user_context_properties_and_empty_user_id() {
-        let mut event = sample_track_event(
-            "event-name".to_string(),
+    fn page_with_ip_modes() {
+        let event = sample_page_event(
+            Some(Consent::Granted),
+            "abc".to_string(),
+            "fr".to_string(),
+            true,
+        );
+        let result = SegmentComponent::page(event, sample_settings()).unwrap();
+        let body: serde_json::Value = serde_json::from_str(&result.body).unwrap();
+        assert_eq!(body["context"]["ip"], "192.168.0.1");
+        assert_eq!(result.forward_client_headers, true);
+
+        for (mode, ip) in [
+            ("full", "192.168.0.1"),
+            ("truncated", "192.168.0.0"),
+            ("omitted", "0.0.0.0"),
+        ] {
+            let event = sample_page_event(
+                Some(Consent::Granted),
+                "abc".to_string(),
+                "fr".to_string(),
+                true,
+            );
+            let mut settings = sample_settings();
+            settings.push(("segment_ip_mode".to_string(), mode.to_string()));
+            let result = SegmentComponent::page(event, settings).unwrap();
+            let body: serde_json::Value = serde_json::from_str(&result.body).unwrap();
+            assert_eq!(body["context"]["ip"], ip);
+            assert_eq!(result.forward_client_headers, mode == "full");
+        }
+    }
+
+    #[test]
+    fn page_with_forward_client_headers() {
+        let mut settings = sample_settings();
+        settings.push(("segment_ip_mode".to_string(), "omitted".to_string()));
+        settings.push((
+            "segment_forward_client_headers".to_string(),
+            "true".to_string(),
+        ));
+        let event = sample_page_event(
+            Some(Consent::Granted),
+            "abc".to_string(),
+            "fr".to_string(),
+            true,
+        );
+        let result = SegmentComponent::page(event, settings).unwrap();
+        assert_eq!(result.forward_client_headers, true);
+
+        let mut settings = sample_settings();
+        settings.push((
+            "segment_forward_client_headers".to_string(),
+            "false".to_string(),
+        ));
+        let event = sample_page_event(
+            Some(Consent::Granted),
+            "abc".to_string(),
+            "fr".to_string(),
+            true,
+        );
+        let result = SegmentComponent::page(event, settings).unwrap();
+        assert_eq!(result.forward_client_headers, false);
+
+        // anonymized events don't forward the client IP either
+        let mut settings = sample_settings();
+        settings.push((
+            "segment_anonymize_without_consent".to_string(),
+            "true".to_string(),
+        ));
+        let event = sample_page_event(
+            Some(Consent::Denied),
+            "abc".to_string(),
+            "fr".to_string(),
+            true,
+        );
+        let result = SegmentComponent::page(event, settings).unwrap();
+        assert_eq!(result.forward_client_headers, false);
+    }
+
+    #[test]
+    fn page_with_invalid_ip_mode_fails() {
+        let event = sample_page_event(
+            Some(Consent::Granted),
+            "abc".to_string(),
+            "fr".to_string(),
+            true,
+        );
+        let mut settings = sample_settings();
+        settings.push(("segment_ip_mode".to_string(), "hashed".to_string()));
+        let result = SegmentComponent::page(event, settings);
+
+        assert_eq!(result.is_err(), true);
+    }
+
+    #[test]
+    fn page_with_anonymous_id_strategies() {
+        let anonymous_id = |strategy: &str| {
+            let mut event = sample_page_event(
+                Some(Consent::Granted),
+                "abc".to_string(),
+                "fr".to_string(),
+                true,
+            );
+            event.context.user.anonymous_id = "".to_string();
+            let mut settings = sample_settings();
+            settings.push((
+                "segment_anonymous_id_strategy".to_string(),
+                strategy.to_string(),
+            ));
+            settings.push(("segment_anonymization_salt".to_string(), "salt".to_string()));
+            let result = SegmentComponent::page(event, settings);
+            let body: serde_json::Value = serde_json::from_str(&result.unwrap().body).unwrap();
+            body["anonymousId"].as_str().unwrap().to_string()
+        };
+
+        assert_eq!(anonymous_id(""), "abc");
+        assert_eq!(anonymous_id("edgee_id"), "abc");
+        assert_eq!(
+            anonymous_id("hashed_edgee_id"),
+            segment_payload::sha256_hex("salt:abc")
+        );
+        assert_eq!(
+            anonymous_id("cookieless"),
+            privacy::cookieless_id(
+                "salt",
+                Utc.timestamp_micros(123).unwrap(),
+                "192.168.0.1",
+                "Chrome"
+            )
+        );
+    }
+
+    #[test]
+    fn page_with_anonymous_id_keeps_it() {
+        let event = sample_page_event(
+            Some(Consent::Granted),
+            "abc".to_string(),
+            "fr".to_string(),
+            true,
+        );
+        let mut settings = sample_settings();
+        settings.push((
+            "segment_anonymous_id_strategy".to_string(),
+            "cookieless".to_string(),
+        ));
+        let result = SegmentComponent::page(event, settings);
+        let body: serde_json::Value = serde_json::from_str(&result.unwrap().body).unwrap();
+
+        assert_eq!(body["anonymousId"], "456");
+    }
+
+    #[test]
+    fn page_with_invalid_anonymous_id_strategy_fails() {
+        let event = sample_page_event(
+            Some(Consent::Granted),
+            "abc".to_string(),
+            "fr".to_string(),
+            true,
+        );
+        let mut settings = sample_settings();
+        settings.push((
+            "segment_anonymous_id_strategy".to_string(),
+            "fingerprint".to_string(),
+        ));
+        let result = SegmentComponent::page(event, settings);
+
+        assert_eq!(result.is_err(), true);
+    }
+
+    #[test]
+    fn page_with_consent_categories() {
+        let mut settings = sample_settings();
+        settings.push((
+            "segment_consent_categories".to_string(),
+            "Analytics, Advertising,Necessary:true".to_string(),
+        ));
+
+        let event = sample_page_event(
+            Some(Consent::Granted),
+            "abc".to_string(),
+            "fr".to_string(),
+            true,
+        );
+        let result = SegmentComponent::page(event, settings.clone());
+        let body: serde_json::Value = serde_json::from_str(&result.unwrap().body).unwrap();
+        assert_eq!(
+            body["context"]["consent"],
+            serde_json::json!({"categoryPreferences": {"Analytics": true, "Advertising": true, "Necessary": true}})
+        );
+
+        for consent in [Consent::Denied, Consent::Pending] {
+            let event = sample_page_event(Some(consent), "abc".to_string(), "fr".to_string(), true);
+            let result = SegmentComponent::page(event, settings.clone());
+            let body: serde_json::Value = serde_json::from_str(&result.unwrap().body).unwrap();
+            assert_eq!(
+                body["context"]["consent"],
+                serde_json::json!({"categoryPreferences": {"Analytics": false, "Advertising": false, "Necessary": true}})
+            );
+        }
+
+        let event = sample_page_event(None, "abc".to_string(), "fr".to_string(), true);
+        let result = SegmentComponent::page(event, settings);
+        let body: serde_json::Value = serde_json::from_str(&result.unwrap().body).unwrap();
+        assert_eq!(body["context"].get("consent"), None);
+    }
+
+    #[test]
+    fn page_with_invalid_consent_categories_fails() {
+        let event = sample_page_event(
+            Some(Consent::Granted),
+            "abc".to_string(),
+            "fr".to_string(),
+            true,
+        );
+        let mut settings = sample_settings();
+        settings.push((
+            "segment_consent_categories".to_string(),
+            "Analytics:maybe".to_string(),
+        ));
+        let result = SegmentComponent::page(event, settings);
+
+        assert_eq!(result.is_err(), true);
+    }
+
+    #[test]
+    fn page_without_consent() {
+        let event = sample_page_event(None, "abc".to_string(), "fr".to_string(), true);
+        let settings = sample_settings();
+        let result = SegmentComponent::page(event, settings);
+
+        assert_eq!(result.is_err(), false);
+        let edgee_request = result.unwrap();
+        assert_eq!(edgee_request.method, HttpMethod::Post);
+        assert!(!edgee_request.body.is_empty());
+    }
+
+    #[test]
+    fn page_with_edgee_id_uuid() {
+        let event = sample_page_event(
+            Some(Consent::Granted),
+            Uuid::new_v4().to_string(),
+            "fr".to_string(),
+            true,
+        );
+        let settings = sample_settings();
+        let result = SegmentComponent::page(event, settings);
+
+        assert_eq!(result.is_err(), false);
+        let edgee_request = result.unwrap();
+        assert_eq!(edgee_request.method, HttpMethod::Post);
+        assert!(!edgee_request.body.is_empty());
+    }
+
+    #[test]
+    fn page_with_empty_locale() {
+        let event = sample_page_event(
+            Some(Consent::Granted),
+            Uuid::new_v4().to_string(),
+            "".to_string(),
+            true,
+        );
+
+        let settings = sample_settings();
+        let result = SegmentComponent::page(event, settings);
+
+        assert_eq!(result.is_err(), false);
+        let edgee_request = result.unwrap();
+        assert_eq!(edgee_request.method, HttpMethod::Post);
+        assert!(!edgee_request.body.is_empty());
+    }
+
+    #[test]
+    fn page_without_context_page_data() {
+        let event = sample_page_event_without_context_page_data(
+            Some(Consent::Granted),
+            Uuid::new_v4().to_string(),
+            "".to_string(),
+            true,
+        );
+
+        let settings = sample_settings();
+        let result = SegmentComponent::page(event, settings);
+
+        assert_eq!(result.is_err(), false);
+        let edgee_request = result.unwrap();
+        assert_eq!(edgee_request.method, HttpMethod::Post);
+        assert!(!edgee_request.body.is_empty());
+    }
+
+    #[test]
+    fn page_without_context_campaign_data() {
+        let event = sample_page_event_without_context_campaign_data(
+            Some(Consent::Granted),
+            Uuid::new_v4().to_string(),
+            "".to_string(),
+            true,
+        );
+
+        let settings = sample_settings();
+        let result = SegmentComponent::page(event, settings);
+
+        assert_eq!(result.is_err(), false);
+        let edgee_request = result.unwrap();
+        assert_eq!(edgee_request.method, HttpMethod::Post);
+        assert!(!edgee_request.body.is_empty());
+    }
+
+    #[test]
+    fn page_not_session_start() {
+        let event = sample_page_event(None, Uuid::new_v4().to_string(), "".to_string(), false);
+        let settings = sample_settings();
+        let result = SegmentComponent::page(event, settings);
+
+        assert_eq!(result.is_err(), false);
+        let edgee_request = result.unwrap();
+        assert_eq!(edgee_request.method, HttpMethod::Post);
+        assert!(!edgee_request.body.is_empty());
+    }
+
+    #[test]
+    fn page_without_project_id_fails() {
+        let event = sample_page_event(None, "abc".to_string(), "fr".to_string(), true);
+        let settings: Vec<(String, String)> = vec![]; // empty
+        let result = SegmentComponent::page(event, settings); // this should panic!
+        assert_eq!(result.is_err(), true);
+    }
+
+    #[test]
+    fn page_without_write_key_fails() {
+        let event = sample_page_event(None, "abc".to_string(), "fr".to_string(), true);
+        let settings: Vec<(String, String)> = vec![
+            ("segment_project_id".to_string(), "abc".to_string()), // only project ID
+        ];
+        let result = SegmentComponent::page(event, settings); // this should panic!
+        assert_eq!(result.is_err(), true);
+    }
+
+    #[test]
+    fn page_with_name_and_category() {
+        let event = sample_page_event(None, "abc".to_string(), "fr".to_string(), true);
+        let result = SegmentComponent::page(event, sample_settings());
+
+        let body: serde_json::Value = serde_json::from_str(&result.unwrap().body).unwrap();
+        assert_eq!(body["name"], "page name");
+        assert_eq!(body["category"], "category");
+    }
+
+    #[test]
+    fn page_without_name() {
+        let mut event = sample_page_event(None, "abc".to_string(), "fr".to_string(), true);
+        if let Data::Page(ref mut data) = event.data {
+            data.name = "".to_string();
+            data.category = "".to_string();
+        }
+        let result = SegmentComponent::page(event, sample_settings());
+
+        let body: serde_json::Value = serde_json::from_str(&result.unwrap().body).unwrap();
+        assert_eq!(body.get("name"), None);
+        assert_eq!(body.get("category"), None);
+    }
+
+    #[test]
+    fn page_name_fallback() {
+        let mut event = sample_page_event(None, "abc".to_string(), "fr".to_string(), true);
+        if let Data::Page(ref mut data) = event.data {
+            data.name = "".to_string();
+        }
+
+        let mut settings = sample_settings();
+        settings.push((
+            "segment_page_name_fallback".to_string(),
+            "title".to_string(),
+        ));
+        let result = SegmentComponent::page(event.clone(), settings);
+        let body: serde_json::Value = serde_json::from_str(&result.unwrap().body).unwrap();
+        assert_eq!(body["name"], "page title");
+
+        let mut settings = sample_settings();
+        settings.push((
+            "segment_page_name_fallback".to_string(),
+            " Path ".to_string(),
+        ));
+        let result = SegmentComponent::page(event, settings);
+        let body: serde_json::Value = serde_json::from_str(&result.unwrap().body).unwrap();
+        assert_eq!(body["name"], "/full-path");
+    }
+
+    #[test]
+    fn page_name_rules() {
+        let mut event = sample_page_event(None, "abc".to_string(), "fr".to_string(), true);
+        if let Data::Page(ref mut data) = event.data {
+            data.name = "".to_string();
+            data.path = "/checkout/payment".to_string();
+        }
+        let mut settings = sample_settings();
+        settings.push((
+            "segment_page_name_rules".to_string(),
+            "/products:Product,/checkout:Checkout".to_string(),
+        ));
+        settings.push((
+            "segment_page_name_fallback".to_string(),
+            "title".to_string(),
+        ));
+        let result = SegmentComponent::page(event, settings);
+
+        let body: serde_json::Value = serde_json::from_str(&result.unwrap().body).unwrap();
+        assert_eq!(body["name"], "Checkout");
+    }
+
+    #[test]
+    fn page_with_context_traits() {
+        let mut event = sample_page_event(None, "abc".to_string(), "fr".to_string(), true);
+        event
+            .context
+            .user
+            .properties
+            .push(("zip".to_string(), "01234".to_string()));
+        let mut settings = sample_settings();
+        settings.push((
+            "segment_property_types".to_string(),
+            "prop2:string".to_string(),
+        ));
+        let result = SegmentComponent::page(event, settings);
+
+        let body: serde_json::Value = serde_json::from_str(&result.unwrap().body).unwrap();
+        assert_eq!(
+            body["context"]["traits"],
+            serde_json::json!({"prop1": "value1", "prop2": "10", "zip": "01234"})
+        );
+    }
+
+    #[test]
+    fn page_with_location() {
+        let event = sample_page_event(None, "abc".to_string(), "fr".to_string(), true);
+        let result = SegmentComponent::page(event, sample_settings());
+
+        let body: serde_json::Value = serde_json::from_str(&result.unwrap().body).unwrap();
+        assert_eq!(
+            body["context"]["location"],
+            serde_json::json!({"city": "Paris", "country": "France", "region": "West Europe"})
+        );
+    }
+
+    #[test]
+    fn page_with_country_code_only() {
+        let mut event = sample_page_event(None, "abc".to_string(), "fr".to_string(), true);
+        event.context.client.city = "".to_string();
+        event.context.client.country_name = "".to_string();
+        event.context.client.region = "".to_string();
+        let result = SegmentComponent::page(event, sample_settings());
+
+        let body: serde_json::Value = serde_json::from_str(&result.unwrap().body).unwrap();
+        assert_eq!(
+            body["context"]["location"],
+            serde_json::json!({"country": "FR"})
+        );
+    }
+
+    #[test]
+    fn page_with_geolocation_disabled() {
+        let event = sample_page_event(None, "abc".to_string(), "fr".to_string(), true);
+        let mut settings = sample_settings();
+        settings.push((
+            "segment_disable_geolocation".to_string(),
+            "true".to_string(),
+        ));
+        let result = SegmentComponent::page(event, settings);
+
+        let result = result.unwrap();
+        assert_eq!(result.forward_client_headers, false);
+        let body: serde_json::Value = serde_json::from_str(&result.body).unwrap();
+        assert_eq!(body["context"].get("location"), None);
+        assert_eq!(body["context"]["ip"], "0.0.0.0");
+    }
+
+    #[test]
+    fn page_with_geolocation_disabled_and_forwarded_headers() {
+        let event = sample_page_event(None, "abc".to_string(), "fr".to_string(), true);
+        let mut settings = sample_settings();
+        settings.push((
+            "segment_disable_geolocation".to_string(),
+            "true".to_string(),
+        ));
+        settings.push(("segment_ip_mode".to_string(), "full".to_string()));
+        settings.push((
+            "segment_forward_client_headers".to_string(),
+            "true".to_string(),
+        ));
+        let result = SegmentComponent::page(event, settings).unwrap();
+
+        assert_eq!(result.forward_client_headers, false);
+        let body: serde_json::Value = serde_json::from_str(&result.body).unwrap();
+        assert_eq!(body["context"]["ip"], "0.0.0.0");
+    }
+
+    #[test]
+    fn page_with_user_agent_data() {
+        let mut event = sample_page_event(None, "abc".to_string(), "fr".to_string(), true);
+        event.context.client.user_agent_version_list =
+            r#""Chromium";v="128", "Not;A=Brand";v="24", "Google Chrome";v="128""#.to_string();
+        event.context.client.user_agent_full_version_list =
+            "Chromium;128.0.6613.120|Not;A=Brand;24.0.0.0|Google Chrome;128.0.6613.120".to_string();
+        event.context.client.user_agent_mobile = "?0".to_string();
+        event.context.client.user_agent_architecture = r#""arm""#.to_string();
+        event.context.client.user_agent_model = "".to_string();
+        let result = SegmentComponent::page(event, sample_settings());
+
+        let body: serde_json::Value = serde_json::from_str(&result.unwrap().body).unwrap();
+        assert_eq!(
+            body["context"]["userAgentData"],
+            serde_json::json!({
+                "brands": [
+                    {"brand": "Chromium", "version": "128"},
+                    {"brand": "Not;A=Brand", "version": "24"},
+                    {"brand": "Google Chrome", "version": "128"}
+                ],
+                "fullVersionList": [
+                    {"brand": "Chromium", "version": "128.0.6613.120"},
+                    {"brand": "Not;A=Brand", "version": "24.0.0.0"},
+                    {"brand": "Google Chrome", "version": "128.0.6613.120"}
+                ],
+                "mobile": false,
+                "platform": "MacOS",
+                "platformVersion": "latest",
+                "architecture": "arm",
+                "bitness": "64"
+            })
+        );
+    }
+
+    #[test]
+    fn page_with_device() {
+        let mut event = sample_page_event(None, "abc".to_string(), "fr".to_string(), true);
+        event.context.client.user_agent = "Mozilla/5.0 (Linux; Android 14; Pixel 8 Build/AP1A.240505.004) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/128.0.0.0 Mobile Safari/537.36".to_string();
+        event.context.client.user_agent_model = "".to_string();
+        let result = SegmentComponent::page(event, sample_settings());
+
+        let body: serde_json::Value = serde_json::from_str(&result.unwrap().body).unwrap();
+        assert_eq!(
+            body["context"]["device"],
+            serde_json::json!({"type": "mobile", "model": "Pixel 8", "manufacturer": "Google"})
+        );
+    }
+
+    #[test]
+    fn page_without_client_hints() {
+        let mut event = sample_page_event(None, "abc".to_string(), "fr".to_string(), true);
+        event.context.client.user_agent_version_list = "".to_string();
+        event.context.client.user_agent_full_version_list = "".to_string();
+        event.context.client.user_agent_mobile = "".to_string();
+        event.context.client.user_agent_architecture = "".to_string();
+        event.context.client.user_agent_bitness = "".to_string();
+        event.context.client.user_agent_model = "".to_string();
+        let result = SegmentComponent::page(event, sample_settings());
+
+        let body: serde_json::Value = serde_json::from_str(&result.unwrap().body).unwrap();
+        assert_eq!(body["context"].get("userAgentData"), None);
+    }
+
+    #[test]
+    fn page_with_session() {
+        let mut event = sample_page_event(None, "abc".to_string(), "fr".to_string(), true);
+        event.context.session.session_id = "1727431200".to_string();
+        event.context.session.previous_session_id = "1727344800".to_string();
+        event.context.session.first_seen = 1727344800;
+        event.context.session.last_seen = 0;
+        let result = SegmentComponent::page(event, sample_settings());
+
+        let body: serde_json::Value = serde_json::from_str(&result.unwrap().body).unwrap();
+        assert_eq!(body["context"]["sessionId"], "1727431200");
+        assert_eq!(body["context"]["sessionCount"], 2);
+        assert_eq!(body["context"]["previousSessionId"], "1727344800");
+        assert_eq!(body["context"]["sessionStart"], true);
+        assert_eq!(body["context"]["firstSeen"], "2024-09-26T10:00:00Z");
+        assert_eq!(body["context"].get("lastSeen"), None);
+        assert_eq!(
+            body["integrations"],
+            serde_json::json!({"Amplitude": {"session_id": 1727431200000_i64}})
+        );
+        assert_eq!(body["properties"].get("session_id"), None);
+    }
+
+    #[test]
+    fn page_with_session_in_properties() {
+        let mut event = sample_page_event(None, "abc".to_string(), "fr".to_string(), true);
+        event.context.session.session_id = "1727431200000".to_string();
+        event.context.session.previous_session_id = "".to_string();
+        event.context.session.session_start = false;
+        event.context.session.first_seen = 1727344800;
+        event.context.session.last_seen = 1727431200;
+        let mut settings = sample_settings();
+        settings.push((
+            "segment_session_id_location".to_string(),
+            "properties".to_string(),
+        ));
+        let result = SegmentComponent::page(event, settings);
+
+        let body: serde_json::Value = serde_json::from_str(&result.unwrap().body).unwrap();
+        assert_eq!(body["context"].get("sessionId"), None);
+        assert_eq!(body["properties"]["session_id"], "1727431200000");
+        assert_eq!(body["properties"]["session_count"], 2);
+        assert_eq!(body["properties"].get("previous_session_id"), None);
+        assert_eq!(body["properties"]["session_start"], false);
+        assert_eq!(body["properties"]["first_seen"], "2024-09-26T10:00:00Z");
+        assert_eq!(body["properties"]["last_seen"], "2024-09-27T10:00:00Z");
+        assert_eq!(body["context"].get("firstSeen"), None);
+        assert_eq!(
+            body["integrations"]["Amplitude"]["session_id"],
+            1727431200000_i64
+        );
+    }
+
+    #[test]
+    fn page_with_session_disabled() {
+        let mut event = sample_page_event(None, "abc".to_string(), "fr".to_string(), true);
+        event.context.session.session_id = "1727431200".to_string();
+        let mut settings = sample_settings();
+        settings.push((
+            "segment_session_id_location".to_string(),
+            "none".to_string(),
+        ));
+        let result = SegmentComponent::page(event, settings);
+
+        let body: serde_json::Value = serde_json::from_str(&result.unwrap().body).unwrap();
+        assert_eq!(body.get("integrations"), None);
+        assert_eq!(body["context"].get("sessionId"), None);
+        assert_eq!(body["properties"].get("session_id"), None);
+    }
+
+    #[test]
+    fn page_with_negative_session_id() {
+        for session_id in ["-9223372036854775808", "-1727431200", "0"] {
+            let mut event = sample_page_event(None, "abc".to_string(), "fr".to_string(), true);
+            event.context.session.session_id = session_id.to_string();
+            let result = SegmentComponent::page(event, sample_settings());
+
+            let body: serde_json::Value = serde_json::from_str(&result.unwrap().body).unwrap();
+            assert_eq!(body.get("integrations"), None);
+            assert_eq!(body["context"]["sessionId"], session_id);
+        }
+    }
+
+    #[test]
+    fn page_with_non_numeric_session_id() {
+        let event = sample_page_event(None, "abc".to_string(), "fr".to_string(), true);
+        let mut settings = sample_settings();
+        settings.push((
+            "segment_session_id_location".to_string(),
+            "none".to_string(),
+        ));
+        let result = SegmentComponent::page(event, settings);
+
+        let body: serde_json::Value = serde_json::from_str(&result.unwrap().body).unwrap();
+        assert_eq!(body.get("integrations"), None);
+        assert_eq!(body["context"].get("sessionId"), None);
+    }
+
+    #[test]
+    fn page_with_lifecycle_events() {
+        let mut event = sample_page_event(None, "abc".to_string(), "fr".to_string(), true);
+        event.context.session.session_count = 1;
+        event.context.session.previous_session_id = "".to_string();
+        event.context.session.first_seen = 1727431200;
+        let mut settings = sample_settings();
+        settings.push((
+            "segment_session_started_event".to_string(),
+            "true".to_string(),
+        ));
+        settings.push(("segment_first_visit_event".to_string(), "true".to_string()));
+        settings.push((
+            "segment_first_visit_event_name".to_string(),
+            "First Seen".to_string(),
+        ));
+        let result = SegmentComponent::page(event, settings);
+
+        let edgee_request = result.unwrap();
+        assert_eq!(edgee_request.url, "https://api.segment.io/v1/batch");
+        let body: serde_json::Value = serde_json::from_str(&edgee_request.body).unwrap();
+        assert_eq!(body["batch"].as_array().unwrap().len(), 3);
+        assert_eq!(body["batch"][0]["type"], "page");
+        assert_eq!(body["batch"][1]["type"], "track");
+        assert_eq!(body["batch"][1]["event"], "Session Started");
+        assert_eq!(
+            body["batch"][1]["properties"],
+            serde_json::json!({"session_id": "random", "session_count": 1})
+        );
+        assert_eq!(body["batch"][2]["event"], "First Seen");
+        assert_eq!(
+            body["batch"][2]["properties"],
+            serde_json::json!({"first_seen": "2024-09-27T10:00:00Z"})
+        );
+        assert_ne!(body["batch"][1]["messageId"], body["batch"][2]["messageId"]);
+    }
+
+    #[test]
+    fn page_with_lifecycle_events_on_returning_session() {
+        let event = sample_page_event(None, "abc".to_string(), "fr".to_string(), true);
+        let mut settings = sample_settings();
+        settings.push((
+            "segment_session_started_event".to_string(),
+            "true".to_string(),
+        ));
+        settings.push(("segment_first_visit_event".to_string(), "true".to_string()));
+        let result = SegmentComponent::page(event, settings);
+
+        let body: serde_json::Value = serde_json::from_str(&result.unwrap().body).unwrap();
+        assert_eq!(body["batch"].as_array().unwrap().len(), 2);
+        assert_eq!(body["batch"][1]["event"], "Session Started");
+        assert_eq!(
+            body["batch"][1]["properties"]["previous_session_id"],
+            "random"
+        );
+    }
+
+    #[test]
+    fn page_with_first_visit_event() {
+        let mut settings = sample_settings();
+        settings.push(("segment_first_visit_event".to_string(), "true".to_string()));
+
+        // an unset first_seen is not sent
+        let mut event = sample_page_event(None, "abc".to_string(), "fr".to_string(), true);
+        event.context.session.session_count = 1;
+        event.context.session.first_seen = 0;
+        let result = SegmentComponent::page(event, settings.clone());
+
+        let body: serde_json::Value = serde_json::from_str(&result.unwrap().body).unwrap();
+        assert_eq!(body["batch"][1]["event"], "First Visit");
+        assert_eq!(body["batch"][1]["properties"], serde_json::json!({}));
+
+        // an unknown session count is not a first visit
+        let mut event = sample_page_event(None, "abc".to_string(), "fr".to_string(), true);
+        event.context.session.session_count = 0;
+        let result = SegmentComponent::page(event, settings);
+
+        assert_eq!(result.unwrap().url, "https://api.segment.io/v1/page");
+    }
+
+    #[test]
+    fn page_with_lifecycle_events_not_session_start() {
+        let event = sample_page_event(None, "abc".to_string(), "fr".to_string(), false);
+        let mut settings = sample_settings();
+        settings.push((
+            "segment_session_started_event".to_string(),
+            "true".to_string(),
+        ));
+        let result = SegmentComponent::page(event, settings);
+
+        assert_eq!(result.unwrap().url, "https://api.segment.io/v1/page");
+    }
+
+    #[test]
+    fn page_with_eu_region() {
+        let event = sample_page_event(None, "abc".to_string(), "fr".to_string(), true);
+        let mut settings = sample_settings();
+        settings.push(("segment_region".to_string(), "EU".to_string()));
+        let result = SegmentComponent::page(event, settings);
+
+        assert_eq!(result.is_err(), false);
+        assert_eq!(
+            result.unwrap().url,
+            "https://events.eu1.segmentapis.com/v1/page"
+        );
+    }
+
+    #[test]
+    fn page_with_endpoint_override() {
+        let event = sample_page_event(None, "abc".to_string(), "fr".to_string(), true);
+        let mut settings = sample_settings();
+        settings.push(("segment_region".to_string(), "eu".to_string()));
+        settings.push((
+            "segment_endpoint".to_string(),
+            "http://localhost:8080/".to_string(),
+        ));
+        let result = SegmentComponent::page(event, settings);
+
+        assert_eq!(result.is_err(), false);
+        assert_eq!(result.unwrap().url, "http://localhost:8080/v1/page");
+    }
+
+    #[test]
+    fn page_with_invalid_region_fails() {
+        let event = sample_page_event(None, "abc".to_string(), "fr".to_string(), true);
+        let mut settings = sample_settings();
+        settings.push(("segment_region".to_string(), "apac".to_string()));
+        let result = SegmentComponent::page(event, settings);
+        assert_eq!(result.is_err(), true);
+    }
+
+    #[test]
+    fn page_with_invalid_endpoint_fails() {
+        let event = sample_page_event(None, "abc".to_string(), "fr".to_string(), true);
+        let mut settings = sample_settings();
+        settings.push(("segment_endpoint".to_string(), "localhost:8080".to_string()));
+        let result = SegmentComponent::page(event, settings);
+        assert_eq!(result.is_err(), true);
+    }
+
+    #[test]
+    fn page_message_id_is_event_uuid() {
+        let event = sample_page_event(None, "abc".to_string(), "fr".to_string(), true);
+        let uuid = event.uuid.clone();
+        let result = SegmentComponent::page(event, sample_settings());
+
+        let body: serde_json::Value = serde_json::from_str(&result.unwrap().body).unwrap();
+        assert_eq!(body["messageId"], uuid);
+    }
+
+    #[test]
+    fn page_message_id_hashed_per_write_key() {
+        let event = sample_page_event(None, "abc".to_string(), "fr".to_string(), true);
+        let uuid = event.uuid.clone();
+        let mut settings = sample_settings();
+        settings.push(("segment_hash_message_id".to_string(), "true".to_string()));
+
+        let first = SegmentComponent::page(event.clone(), settings.clone()).unwrap();
+        let second = SegmentComponent::page(event.clone(), settings).unwrap();
+        let other_key = SegmentComponent::page(
+            event,
+            vec![
+                ("segment_project_id".to_string(), "abc".to_string()),
+                ("segment_write_key".to_string(), "def".to_string()),
+                ("segment_hash_message_id".to_string(), "true".to_string()),
+            ],
+        )
+        .unwrap();
+
+        let first: serde_json::Value = serde_json::from_str(&first.body).unwrap();
+        let second: serde_json::Value = serde_json::from_str(&second.body).unwrap();
+        let other_key: serde_json::Value = serde_json::from_str(&other_key.body).unwrap();
+        assert_ne!(first["messageId"], uuid);
+        assert_eq!(first["messageId"].as_str().unwrap().len(), 64);
+        assert_eq!(first["messageId"], second["messageId"]);
+        assert_ne!(first["messageId"], other_key["messageId"]);
+    }
+
+    #[test]
+    fn track_with_consent() {
+        let event = sample_track_event(
+            "event-name".to_string(),
+            Some(Consent::Granted),
+            "abc".to_string(),
+            "fr".to_string(),
+            true,
+        );
+        let settings = sample_settings();
+        let result = SegmentComponent::track(event, settings);
+        assert_eq!(result.clone().is_err(), false);
+        let edgee_request = result.unwrap();
+        assert_eq!(edgee_request.method, HttpMethod::Post);
+        assert!(!edgee_request.body.is_empty());
+        assert_eq!(edgee_request.url, "https://api.segment.io/v1/track");
+    }
+
+    #[test]
+    fn track_with_products() {
+        let mut event = sample_track_event(
+            "Order Completed".to_string(),
+            Some(Consent::Granted),
+            "abc".to_string(),
+            "fr".to_string(),
+            true,
+        );
+        if let Data::Track(ref mut data) = event.data {
+            data.properties = vec![
+                ("order_id".to_string(), "50314b8e".to_string()),
+                ("revenue".to_string(), "25.00".to_string()),
+            ];
+            data.products = vec![
+                vec![
+                    ("product_id".to_string(), "507f1f77".to_string()),
+                    ("sku".to_string(), "0045-360".to_string()),
+                    ("name".to_string(), "Monopoly: 3rd Edition".to_string()),
+                    ("price".to_string(), "19".to_string()),
+                    ("quantity".to_string(), "1".to_string()),
+                    ("category".to_string(), "Games".to_string()),
+                    (
+                        "url".to_string(),
+                        "https://example.com/monopoly".to_string(),
+                    ),
+                    (
+                        "image_url".to_string(),
+                        "https://example.com/monopoly.jpg".to_string(),
+                    ),
+                    ("currency".to_string(), "USD".to_string()),
+                ],
+                vec![
+                    ("id".to_string(), "505bd76785".to_string()),
+                    ("sku".to_string(), "0046".to_string()),
+                    ("name".to_string(), "Uno Card Game".to_string()),
+                    ("price".to_string(), "3".to_string()),
+                    ("quantity".to_string(), "2".to_string()),
+                    ("brand".to_string(), "Mattel".to_string()),
+                    ("variant".to_string(), "classic".to_string()),
+                    ("coupon".to_string(), "".to_string()),
+                    ("position".to_string(), "2".to_string()),
+                    ("gift_wrap".to_string(), "true".to_string()),
+                    ("currency".to_string(), "USD".to_string()),
+                ],
+            ];
+        }
+        let result = SegmentComponent::track(event, sample_settings());
+
+        let body: serde_json::Value = serde_json::from_str(&result.unwrap().body).unwrap();
+        assert_eq!(body["properties"]["revenue"], 25.0);
+        assert_eq!(body["properties"]["currency"], "USD");
+        assert_eq!(
+            body["properties"]["products"],
+            serde_json::json!([
+                {
+                    "product_id": "507f1f77",
+                    "sku": "0045-360",
+                    "name": "Monopoly: 3rd Edition",
+                    "price": 19,
+                    "quantity": 1,
+                    "category": "Games",
+                    "url": "https://example.com/monopoly",
+                    "image_url": "https://example.com/monopoly.jpg",
+                    "currency": "USD"
+                },
+                {
+                    "product_id": "505bd76785",
+                    "sku": "0046",
+                    "name": "Uno Card Game",
+                    "price": 3,
+                    "quantity": 2,
+                    "brand": "Mattel",
+                    "variant": "classic",
+                    "position": 2,
+                    "gift_wrap": true,
+                    "currency": "USD"
+                }
+            ])
+        );
+    }
+
+    #[test]
+    fn track_with_product_revenue() {
+        let mut event = sample_track_event(
+            "Order Completed".to_string(),
+            Some(Consent::Granted),
+            "abc".to_string(),
+            "fr".to_string(),
+            true,
+        );
+        if let Data::Track(ref mut data) = event.data {
+            data.properties = vec![("order_id".to_string(), "50314b8e".to_string())];
+            data.products = vec![
+                vec![
+                    ("product_id".to_string(), "507f1f77".to_string()),
+                    ("revenue".to_string(), "19.00".to_string()),
+                    ("currency".to_string(), "USD".to_string()),
+                ],
+                vec![
+                    ("product_id".to_string(), "505bd76785".to_string()),
+                    ("revenue".to_string(), "6.00".to_string()),
+                    ("total".to_string(), "7.20".to_string()),
+                    ("currency".to_string(), "EUR".to_string()),
+                ],
+            ];
+        }
+        let result = SegmentComponent::track(event, sample_settings());
+
+        let body: serde_json::Value = serde_json::from_str(&result.unwrap().body).unwrap();
+        // line-item revenue stays on the products, mixed currencies are not lifted
+        assert_eq!(body["properties"].get("revenue"), None);
+        assert_eq!(body["properties"].get("total"), None);
+        assert_eq!(body["properties"].get("currency"), None);
+        assert_eq!(
+            body["properties"]["products"],
+            serde_json::json!([
+                {
+                    "product_id": "507f1f77",
+                    "revenue": 19.0,
+                    "currency": "USD"
+                },
+                {
+                    "product_id": "505bd76785",
+                    "revenue": 6.0,
+                    "total": 7.2,
+                    "currency": "EUR"
+                }
+            ])
+        );
+    }
+
+    #[test]
+    fn track_without_products() {
+        let event = sample_track_event(
+            "event-name".to_string(),
+            Some(Consent::Granted),
+            "abc".to_string(),
+            "fr".to_string(),
+            true,
+        );
+        let result = SegmentComponent::track(event, sample_settings());
+
+        let body: serde_json::Value = serde_json::from_str(&result.unwrap().body).unwrap();
+        assert_eq!(body["properties"].get("products"), None);
+    }
+
+    #[test]
+    fn track_with_property_types() {
+        let mut event = sample_track_event(
+            "event-name".to_string(),
+            Some(Consent::Granted),
+            "abc".to_string(),
+            "fr".to_string(),
+            true,
+        );
+        if let Data::Track(ref mut data) = event.data {
+            data.properties = vec![
+                ("zip".to_string(), "01234".to_string()),
+                ("phone".to_string(), "33612345678".to_string()),
+                ("score".to_string(), "NaN".to_string()),
+                ("ratio".to_string(), "inf".to_string()),
+                ("quantity".to_string(), "3".to_string()),
+            ];
+        }
+        let mut settings = sample_settings();
+        settings.push((
+            "segment_property_types".to_string(),
+            "phone:string,ratio:float".to_string(),
+        ));
+        let result = SegmentComponent::track(event, settings);
+
+        let body: serde_json::Value = serde_json::from_str(&result.unwrap().body).unwrap();
+        assert_eq!(
+            body["properties"],
+            serde_json::json!({
+                "zip": "01234",
+                "phone": "33612345678",
+                "score": "NaN",
+                "ratio": "inf",
+                "quantity": 3
+            })
+        );
+    }
+
+    #[test]
+    fn track_with_json_and_dotted_properties() {
+        let mut event = sample_track_event(
+            "event-name".to_string(),
+            Some(Consent::Granted),
+            "abc".to_string(),
+            "fr".to_string(),
+            true,
+        );
+        if let Data::Track(ref mut data) = event.data {
+            data.properties = vec![
+                ("address".to_string(), r#"{"city": "Paris"}"#.to_string()),
+                ("address.zip".to_string(), "75001".to_string()),
+                ("tags".to_string(), r#"["new", "promo"]"#.to_string()),
+            ];
+        }
+        let mut settings = sample_settings();
+        settings.push((
+            "segment_parse_json_properties".to_string(),
+            "true".to_string(),
+        ));
+        settings.push((
+            "segment_unflatten_properties".to_string(),
+            "true".to_string(),
+        ));
+        settings.push((
+            "segment_property_types".to_string(),
+            "address.zip:string".to_string(),
+        ));
+        let result = SegmentComponent::track(event, settings);
+
+        let body: serde_json::Value = serde_json::from_str(&result.unwrap().body).unwrap();
+        assert_eq!(
+            body["properties"],
+            serde_json::json!({
+                "address": {"city": "Paris", "zip": "75001"},
+                "tags": ["new", "promo"]
+            })
+        );
+    }
+
+    #[test]
+    fn track_with_invalid_property_types_fails() {
+        let event = sample_track_event(
+            "event-name".to_string(),
+            Some(Consent::Granted),
+            "abc".to_string(),
+            "fr".to_string(),
+            true,
+        );
+        let mut settings = sample_settings();
+        settings.push(("segment_property_types".to_string(), "zip:zip".to_string()));
+        let result = SegmentComponent::track(event, settings);
+
+        assert_eq!(result.is_err(), true);
+    }
+
+    #[test]
+    fn track_with_ga4_preset() {
+        let mut event = sample_track_event(
+            "purchase".to_string(),
+            Some(Consent::Granted),
+            "abc".to_string(),
+            "fr".to_string(),
+            true,
+        );
+        if let Data::Track(ref mut data) = event.data {
+            data.properties = vec![
+                ("transaction_id".to_string(), "T_12345".to_string()),
+                ("value".to_string(), "72.05".to_string()),
+                ("currency".to_string(), "USD".to_string()),
+            ];
+        }
+        let mut settings = sample_settings();
+        settings.push(("segment_event_name_preset".to_string(), "ga4".to_string()));
+        let result = SegmentComponent::track(event, settings);
+
+        let body: serde_json::Value = serde_json::from_str(&result.unwrap().body).unwrap();
+        assert_eq!(body["event"], "Order Completed");
+        assert_eq!(
+            body["properties"],
+            serde_json::json!({"order_id": "T_12345", "revenue": 72.05, "currency": "USD"})
+        );
+    }
+
+    #[test]
+    fn track_with_ga4_preset_keeps_unknown_events() {
+        let event = sample_track_event(
+            "event-name".to_string(),
+            Some(Consent::Granted),
+            "abc".to_string(),
+            "fr".to_string(),
+            true,
+        );
+        let mut settings = sample_settings();
+        settings.push(("segment_event_name_preset".to_string(), "ga4".to_string()));
+        let result = SegmentComponent::track(event, settings);
+
+        let body: serde_json::Value = serde_json::from_str(&result.unwrap().body).unwrap();
+        assert_eq!(body["event"], "event-name");
+    }
+
+    #[test]
+    fn track_with_event_name_mapping() {
+        let event = sample_track_event(
+            "add_to_cart".to_string(),
+            Some(Consent::Granted),
+            "abc".to_string(),
+            "fr".to_string(),
+            true,
+        );
+        let mut settings = sample_settings();
+        settings.push(("segment_event_name_preset".to_string(), "ga4".to_string()));
+        settings.push((
+            "segment_event_name_mapping".to_string(),
+            "signup:Signed Up, add_to_cart:Added To Basket".to_string(),
+        ));
+        let result = SegmentComponent::track(event, settings);
+
+        let body: serde_json::Value = serde_json::from_str(&result.unwrap().body).unwrap();
+        assert_eq!(body["event"], "Added To Basket");
+    }
+
+    #[test]
+    fn track_with_invalid_preset_fails() {
+        let event = sample_track_event(
+            "purchase".to_string(),
+            Some(Consent::Granted),
+            "abc".to_string(),
+            "fr".to_string(),
+            true,
+        );
+        let mut settings = sample_settings();
+        settings.push(("segment_event_name_preset".to_string(), "ua".to_string()));
+        let result = SegmentComponent::track(event, settings);
+
+        assert_eq!(result.is_err(), true);
+    }
+
+    #[test]
+    fn track_with_group() {
+        let mut event = sample_track_event(
+            "event-name".to_string(),
+            Some(Consent::Granted),
+            "abc".to_string(),
+            "fr".to_string(),
+            true,
+        );
+        if let Data::Track(ref mut data) = event.data {
+            data.properties
+                .push(("group_id".to_string(), "acme".to_string()));
+            data.properties
+                .push(("group_name".to_string(), "Acme Inc".to_string()));
+            data.properties
+                .push(("group_employees".to_string(), "42".to_string()));
+        }
+        let mut settings = sample_settings();
+        settings.push(("segment_group_id_key".to_string(), "group_id".to_string()));
+        settings.push((
+            "segment_group_traits_prefix".to_string(),
+            "group_".to_string(),
+        ));
+        let result = SegmentComponent::track(event, settings);
+
+        let edgee_request = result.unwrap();
+        assert_eq!(edgee_request.url, "https://api.segment.io/v1/batch");
+        let body: serde_json::Value = serde_json::from_str(&edgee_request.body).unwrap();
+        assert_eq!(body["batch"].as_array().unwrap().len(), 2);
+        assert_eq!(body["batch"][0]["type"], "track");
+        assert_eq!(body["batch"][1]["type"], "group");
+        assert_eq!(body["batch"][1]["groupId"], "acme");
+        assert_eq!(
+            body["batch"][1]["traits"],
+            serde_json::json!({"name": "Acme Inc", "employees": 42})
+        );
+    }
+
+    #[test]
+    fn track_without_group_id_property() {
+        let event = sample_track_event(
+            "event-name".to_string(),
+            Some(Consent::Granted),
+            "abc".to_string(),
+            "fr".to_string(),
+            true,
+        );
+        let mut settings = sample_settings();
+        settings.push(("segment_group_id_key".to_string(), "group_id".to_string()));
+        let result = SegmentComponent::track(event, settings);
+
+        assert_eq!(result.unwrap().url, "https://api.segment.io/v1/track");
+    }
+
+    #[test]
+    fn track_with_empty_name_fails() {
+        let event = sample_track_event(
+            "".to_string(),
+            Some(Consent::Granted),
+            "abc".to_string(),
+            "fr".to_string(),
+            true,
+        );
+        let settings = sample_settings();
+        let result = SegmentComponent::track(event, settings);
+        assert_eq!(result.is_err(), true);
+    }
+
+    #[test]
+    fn user_event() {
+        let event = sample_user_event(
+            Some(Consent::Granted),
+            "abc".to_string(),
+            "fr".to_string(),
+            true,
+        );
+        let settings = sample_settings();
+        let result = SegmentComponent::user(event, settings);
+
+        assert_eq!(result.clone().is_err(), false);
+        let edgee_request = result.unwrap();
+        assert_eq!(edgee_request.url, "https://api.segment.io/v1/identify");
+        let body: serde_json::Value = serde_json::from_str(&edgee_request.body).unwrap();
+        assert_eq!(body["type"], "identify");
+    }
+
+    #[test]
+    fn user_event_ids_take_precedence_over_context() {
+        let mut event = sample_user_event(
+            Some(Consent::Granted),
+            "abc".to_string(),
+            "fr".to_string(),
+            true,
+        );
+        event.context.user.user_id = "ctx-user".to_string();
+        event.context.user.anonymous_id = "ctx-anonymous".to_string();
+        let result = SegmentComponent::user(event, sample_settings());
+
+        let body: serde_json::Value = serde_json::from_str(&result.unwrap().body).unwrap();
+        assert_eq!(body["userId"], "123");
+        assert_eq!(body["anonymousId"], "456");
+        assert_eq!(
+            body["traits"],
+            serde_json::json!({"prop1": "value1", "prop2": 10})
+        );
+    }
+
+    #[test]
+    fn user_event_ids_fall_back_to_context() {
+        let mut event = sample_user_event(
+            Some(Consent::Granted),
+            "abc".to_string(),
+            "fr".to_string(),
+            true,
+        );
+        if let Data::User(ref mut data) = event.data {
+            data.anonymous_id = "".to_string();
+        }
+        event.context.user.user_id = "ctx-user".to_string();
+        event.context.user.anonymous_id = "ctx-anonymous".to_string();
+        let result = SegmentComponent::user(event.clone(), sample_settings());
+
+        let body: serde_json::Value = serde_json::from_str(&result.unwrap().body).unwrap();
+        assert_eq!(body["userId"], "123");
+        assert_eq!(body["anonymousId"], "ctx-anonymous");
+
+        if let Data::User(ref mut data) = event.data {
+            data.user_id = "".to_string();
+            data.anonymous_id = "456".to_string();
+        }
+        event.context.user.anonymous_id = "".to_string();
+        let result = SegmentComponent::user(event, sample_settings());
+
+        let body: serde_json::Value = serde_json::from_str(&result.unwrap().body).unwrap();
+        assert_eq!(body["userId"], "ctx-user");
+        assert_eq!(body["anonymousId"], "456");
+    }
+
+    #[test]
+    fn user_event_anonymous_id_falls_back_to_edgee_id() {
+        let mut event =
+            sample_user_event_without_anonymous_id(Some(Consent::Granted), "fr".to_string(), true);
+        event.context.user.user_id = "ctx-user".to_string();
+        let result = SegmentComponent::user(event, sample_settings());
+
+        let body: serde_json::Value = serde_json::from_str(&result.unwrap().body).unwrap();
+        assert_eq!(body["userId"], "123");
+        assert_eq!(body["anonymousId"], "abc");
+    }
+
+    #[test]
+    fn user_event_with_group() {
+        let mut event = sample_user_event(
+            Some(Consent::Granted),
+            "abc".to_string(),
+            "fr".to_string(),
+            true,
+        );
+        event.context.user.user_id = "ctx-user".to_string();
+        if let Data::User(ref mut data) = event.data {
+            data.properties
+                .push(("company_id".to_string(), "acme".to_string()));
+            data.properties
+                .push(("company_plan".to_string(), "enterprise".to_string()));
+        }
+        let mut settings = sample_settings();
+        settings.push(("segment_group_id_key".to_string(), "company_id".to_string()));
+        settings.push((
+            "segment_group_traits_prefix".to_string(),
+            "company_".to_string(),
+        ));
+        let result = SegmentComponent::user(event, settings);
+
+        let edgee_request = result.unwrap();
+        assert_eq!(edgee_request.url, "https://api.segment.io/v1/batch");
+        let body: serde_json::Value = serde_json::from_str(&edgee_request.body).unwrap();
+        assert_eq!(body["batch"][0]["type"], "identify");
+        assert_eq!(body["batch"][1]["type"], "group");
+        assert_eq!(body["batch"][1]["groupId"], "acme");
+        assert_eq!(body["batch"][1]["userId"], "123");
+        assert_eq!(
+            body["batch"][1]["traits"],
+            serde_json::json!({"plan": "enterprise"})
+        );
+        assert_ne!(body["batch"][0]["messageId"], body["batch"][1]["messageId"]);
+    }
+
+    #[test]
+    fn user_event_with_alias() {
+        let event = sample_user_event(
+            Some(Consent::Granted),
+            "abc".to_string(),
+            "fr".to_string(),
+            true,
+        );
+        let mut settings = sample_settings();
+        settings.push(("segment_send_alias".to_string(), "alias".to_string()));
+        let result = SegmentComponent::user(event, settings);
+
+        let edgee_request = result.unwrap();
+        assert_eq!(edgee_request.url, "https://api.segment.io/v1/alias");
+        let body: serde_json::Value = serde_json::from_str(&edgee_request.body).unwrap();
+        assert_eq!(body["type"], "alias");
+        assert_eq!(body["userId"], "123");
+        assert_eq!(body["previousId"], "456");
+        assert_eq!(body.get("anonymousId"), None);
+    }
+
+    #[test]
+    fn user_event_with_alias_and_identify() {
+        let event = sample_user_event(
+            Some(Consent::Granted),
+            "abc".to_string(),
+            "fr".to_string(),
+            true,
+        );
+        let mut settings = sample_settings();
+        settings.push(("segment_send_alias".to_string(), "batch".to_string()));
+        let result = SegmentComponent::user(event, settings);
+
+        let edgee_request = result.unwrap();
+        assert_eq!(edgee_request.url, "https://api.segment.io/v1/batch");
+        let body: serde_json::Value = serde_json::from_str(&edgee_request.body).unwrap();
+        assert_eq!(body["batch"][0]["type"], "alias");
+        assert_eq!(body["batch"][0]["previousId"], "456");
+        assert_eq!(body["batch"][1]["type"], "identify");
+        assert_eq!(body["batch"][1]["traits"]["prop1"], "value1");
+    }
+
+    #[test]
+    fn user_event_without_anonymous_id_skips_alias() {
+        let event =
+            sample_user_event_without_anonymous_id(Some(Consent::Granted), "fr".to_string(), true);
+        let mut settings = sample_settings();
+        settings.push(("segment_send_alias".to_string(), "alias".to_string()));
+        let result = SegmentComponent::user(event, settings);
+
+        assert_eq!(result.unwrap().url, "https://api.segment.io/v1/identify");
+    }
+
+    #[test]
+    fn user_event_with_invalid_alias_mode_fails() {
+        let event = sample_user_event(
+            Some(Consent::Granted),
+            "abc".to_string(),
+            "fr".to_string(),
+            true,
+        );
+        let mut settings = sample_settings();
+        settings.push(("segment_send_alias".to_string(), "always".to_string()));
+        let result = SegmentComponent::user(event, settings);
+
+        assert_eq!(result.is_err(), true);
+    }
+
+    #[test]
+    fn user_event_without_anonymous_id() {
+        let event =
+            sample_user_event_without_anonymous_id(Some(Consent::Granted), "fr".to_string(), true);
+        let settings = sample_settings();
+        let result = SegmentComponent::user(event, settings);
+
+        assert_eq!(result.clone().is_err(), false);
+    }
+
+    #[test]
+    fn user_event_without_ids_fails() {
+        let event = sample_user_event_without_ids(Some(Consent::Granted), "fr".to_string(), true);
+        let settings = sample_settings();
+        let result = SegmentComponent::user(event, settings);
+
+        assert_eq!(result.clone().is_err(), true);
+        assert_eq!(
+            result
+                .clone()
+                .err()
+                .unwrap()
+                .to_string()
+                .contains("is not set"),
+            true
+        );
+    }
+
+    #[test]
+    fn track_event_without_user_context_properties_and_empty_user_id() {
+        let mut event = sample_track_event(
+            "event-name".to_string(),
             Some(Consent::Granted),
             "abc".to_string(),
             "fr".to_string(),
@@ -719,5 +2723,39 @@ mod tests {
         let result = SegmentComponent::track(event, settings);
         //println!("Error: {}", result.clone().err().unwrap().to_string().as_str());
         assert_eq!(result.clone().is_err(), false);
+        let body: serde_json::Value = serde_json::from_str(&result.unwrap().body).unwrap();
+        assert_eq!(body["context"].get("traits"), None);
+    }
+
+    #[test]
+    fn track_with_context_traits() {
+        let event = sample_track_event(
+            "event-name".to_string(),
+            Some(Consent::Granted),
+            "abc".to_string(),
+            "fr".to_string(),
+            true,
+        );
+        let result = SegmentComponent::track(event, sample_settings());
+
+        let body: serde_json::Value = serde_json::from_str(&result.unwrap().body).unwrap();
+        assert_eq!(
+            body["context"]["traits"],
+            serde_json::json!({"prop1": "value1", "prop2": 10})
+        );
+    }
+
+    #[test]
+    fn user_event_without_context_traits() {
+        let event = sample_user_event(
+            Some(Consent::Granted),
+            "abc".to_string(),
+            "fr".to_string(),
+            true,
+        );
+        let result = SegmentComponent::user(event, sample_settings());
+
+        let body: serde_json::Value = serde_json::from_str(&result.unwrap().body).unwrap();
+        assert_eq!(body["context"].get("traits"), None);
     }
 }
@@ -0,0 +1,226 @@
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use crate::segment_payload::sha256_hex;
+
+/// IP sent instead of the client one, as Segment would otherwise geolocate the Edgee servers.
+pub(crate) const OMITTED_IP: &str = "0.0.0.0";
+
+/// Truncate an IP address to its network prefix (/24 for IPv4, /48 for IPv6), so that it
+/// still geolocates to a city but no longer identifies a household.
+pub(crate) fn truncate_ip(ip: &str) -> Option<String> {
+    match ip.trim().parse::<IpAddr>().ok()? {
+        IpAddr::V4(ip) => {
+            let [a, b, c, _] = ip.octets();
+            Some(Ipv4Addr::new(a, b, c, 0).to_string())
+        }
+        IpAddr::V6(ip) => {
+            let segments = ip.segments();
+            Some(Ipv6Addr::new(segments[0], segments[1], segments[2], 0, 0, 0, 0, 0).to_string())
+        }
+    }
+}
+
+/// Salted hash of an identifier, rotated every (UTC) day so that it can't be joined across days.
+pub(crate) fn daily_hash(salt: &str, date: DateTime<Utc>, id: &str) -> String {
+    sha256_hex(&format!("{salt}:{}:{id}", date.format("%Y-%m-%d")))
+}
+
+/// Cookieless visitor id: a salted hash of the day, the IP prefix and the user-agent, so that a
+/// visitor keeps the same id for the day without any identifier stored on their device.
+pub(crate) fn cookieless_id(salt: &str, date: DateTime<Utc>, ip: &str, user_agent: &str) -> String {
+    let ip_prefix = truncate_ip(ip).unwrap_or_default();
+    daily_hash(salt, date, &format!("{ip_prefix}:{user_agent}"))
+}
+
+/// Replace the traits listed in `segment_hashed_traits` with the SHA-256 of their normalized
+/// value, as expected by advertising destinations (Facebook Conversions API, Google Enhanced
+/// Conversions, ...).
+///
+/// Values are trimmed and lowercased, and traits whose key contains `phone` are normalized to
+/// E.164 (phone traits that can't be are dropped rather than hashed). The clear value is kept
+/// under the key suffixed by `segment_hashed_traits_clear_suffix`, if set.
+pub(crate) fn hash_traits(
+    traits: &mut HashMap<String, serde_json::Value>,
+    settings: &HashMap<String, String>,
+) {
+    let Some(keys) = settings.get("segment_hashed_traits") else {
+        return;
+    };
+    let clear_suffix = settings
+        .get("segment_hashed_traits_clear_suffix")
+        .filter(|suffix| !suffix.is_empty());
+    let country_code = settings
+        .get("segment_phone_country_code")
+        .map(|code| code.trim().trim_start_matches('+'))
+        .filter(|code| !code.is_empty());
+
+    for key in keys.split(',').map(str::trim).filter(|key| !key.is_empty()) {
+        let clear = match traits.get(key) {
+            Some(serde_json::Value::String(value)) => value.clone(),
+            Some(serde_json::Value::Number(value)) => value.to_string(),
+            _ => continue,
+        };
+        let normalized = if key.to_lowercase().contains("phone") {
+            normalize_phone(&clear, country_code)
+        } else {
+            Some(clear.trim().to_lowercase()).filter(|value| !value.is_empty())
+        };
+
+        let clear_value = match normalized {
+            Some(normalized) => traits.insert(key.to_string(), sha256_hex(&normalized).into()),
+            // a hash that no destination could match is not worth sending
+            None if key.to_lowercase().contains("phone") => traits.remove(key),
+            None => continue,
+        };
+        if let (Some(clear_value), Some(suffix)) = (clear_value, clear_suffix) {
+            traits.insert(format!("{key}{suffix}"), clear_value);
+        }
+    }
+}
+
+/// Longest national significant number (without trunk prefix) of the usual numbering plans.
+const MAX_NATIONAL_DIGITS: usize = 10;
+
+/// Normalize a phone number to E.164 (`+33612345678`), national numbers needing the default
+/// country code (`segment_phone_country_code`).
+///
+/// Numbers that are not valid E.164 once normalized (too short or too long, or national
+/// without a default country code) are rejected.
+fn normalize_phone(phone: &str, country_code: Option<&str>) -> Option<String> {
+    let digits: String = phone.chars().filter(char::is_ascii_digit).collect();
+    let international = if phone.trim_start().starts_with('+') {
+        digits
+    } else if let Some(international) = digits.strip_prefix("00") {
+        international.to_string()
+    } else if let Some(national) = digits.strip_prefix('0') {
+        format!("{}{national}", country_code?)
+    } else {
+        // without a trunk prefix, the number may already start with the country code
+        // (`1 (555) 123-4567`, or a `33612345678` trait coerced to a number)
+        let country_code = country_code?;
+        if digits.starts_with(country_code) && digits.len() > MAX_NATIONAL_DIGITS {
+            digits
+        } else {
+            format!("{country_code}{digits}")
+        }
+    };
+    ((8..=15).contains(&international.len()) && !international.starts_with('0'))
+        .then(|| format!("+{international}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn truncated_ips() {
+        assert_eq!(truncate_ip("192.168.0.42"), Some("192.168.0.0".to_string()));
+        assert_eq!(
+            truncate_ip("2001:db8:85a3:8d3:1319:8a2e:370:7348"),
+            Some("2001:db8:85a3::".to_string())
+        );
+        assert_eq!(truncate_ip("unknown"), None);
+        assert_eq!(truncate_ip(""), None);
+    }
+
+    #[test]
+    fn daily_hash_rotates() {
+        let morning = Utc.with_ymd_and_hms(2024, 9, 1, 8, 0, 0).unwrap();
+        let evening = Utc.with_ymd_and_hms(2024, 9, 1, 20, 0, 0).unwrap();
+        let next_day = Utc.with_ymd_and_hms(2024, 9, 2, 8, 0, 0).unwrap();
+
+        assert_eq!(
+            daily_hash("salt", morning, "abc"),
+            daily_hash("salt", evening, "abc")
+        );
+        assert_ne!(
+            daily_hash("salt", morning, "abc"),
+            daily_hash("salt", next_day, "abc")
+        );
+        assert_ne!(
+            daily_hash("salt", morning, "abc"),
+            daily_hash("pepper", morning, "abc")
+        );
+        assert_eq!(daily_hash("salt", morning, "abc").len(), 64);
+    }
+
+    #[test]
+    fn cookieless_ids() {
+        let date = Utc.with_ymd_and_hms(2024, 9, 1, 8, 0, 0).unwrap();
+        let id = cookieless_id("salt", date, "192.168.0.1", "Chrome");
+
+        // same network and browser
+        assert_eq!(id, cookieless_id("salt", date, "192.168.0.42", "Chrome"));
+        assert_ne!(id, cookieless_id("salt", date, "192.168.1.1", "Chrome"));
+        assert_ne!(id, cookieless_id("salt", date, "192.168.0.1", "Firefox"));
+        assert_ne!(id, cookieless_id("pepper", date, "192.168.0.1", "Chrome"));
+    }
+
+    #[test]
+    fn phones() {
+        let phone = |phone: &str, country_code| normalize_phone(phone, country_code);
+        assert_eq!(phone("+33 6 12 34 56 78", None).unwrap(), "+33612345678");
+        assert_eq!(phone("0033 6 12 34 56 78", None).unwrap(), "+33612345678");
+        assert_eq!(phone("06.12.34.56.78", Some("33")).unwrap(), "+33612345678");
+        assert_eq!(phone("33612345678", Some("33")).unwrap(), "+33612345678");
+        assert_eq!(phone("(555) 123-4567", Some("1")).unwrap(), "+15551234567");
+        assert_eq!(phone("1-555-123-4567", Some("1")).unwrap(), "+15551234567");
+        assert_eq!(
+            phone("1 (555) 123-4567", Some("1")).unwrap(),
+            "+15551234567"
+        );
+        // not E.164
+        assert_eq!(phone("06 12 34 56 78", None), None);
+        assert_eq!(phone("+33 6 12", None), None);
+        assert_eq!(phone("+33 6 12 34 56 78 90 12 34", None), None);
+        assert_eq!(phone("n/a", Some("33")), None);
+    }
+
+    #[test]
+    fn hashed_traits() {
+        let settings = HashMap::from([
+            (
+                "segment_hashed_traits".to_string(),
+                "email, phone, mobile_phone, work_phone, missing".to_string(),
+            ),
+            (
+                "segment_hashed_traits_clear_suffix".to_string(),
+                "_clear".to_string(),
+            ),
+            ("segment_phone_country_code".to_string(), "+33".to_string()),
+        ]);
+        let mut traits = HashMap::from([
+            ("email".to_string(), " John.Doe@Example.com ".into()),
+            ("phone".to_string(), "06 12 34 56 78".into()),
+            ("mobile_phone".to_string(), 33612345678_u64.into()),
+            ("work_phone".to_string(), "n/a".into()),
+            ("plan".to_string(), "pro".into()),
+        ]);
+        hash_traits(&mut traits, &settings);
+
+        assert_eq!(
+            traits,
+            HashMap::from([
+                (
+                    "email".to_string(),
+                    sha256_hex("john.doe@example.com").into()
+                ),
+                ("email_clear".to_string(), " John.Doe@Example.com ".into()),
+                ("phone".to_string(), sha256_hex("+33612345678").into()),
+                ("phone_clear".to_string(), "06 12 34 56 78".into()),
+                (
+                    "mobile_phone".to_string(),
+                    sha256_hex("+33612345678").into()
+                ),
+                ("mobile_phone_clear".to_string(), 33612345678_u64.into()),
+                // phones that can't be normalized are not hashed
+                ("work_phone_clear".to_string(), "n/a".into()),
+                ("plan".to_string(), "pro".into()),
+            ])
+        );
+    }
+}
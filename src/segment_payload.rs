@@ -1,9 +1,37 @@
 use anyhow::anyhow;
 use chrono::{DateTime, TimeZone, Utc};
 use serde::Serialize;
+use sha2::{Digest, Sha256};
 use std::collections::HashMap;
 
-use crate::exports::edgee::components::data_collection::{Dict, Event};
+use crate::coercion::Coercion;
+use crate::exports::edgee::components::data_collection::{Consent, Event, UserData};
+use crate::privacy;
+use crate::scrubbing::Scrubber;
+use crate::user_agent;
+
+const US_API_HOST: &str = "https://api.segment.io";
+const EU_API_HOST: &str = "https://events.eu1.segmentapis.com";
+
+const REGIONS: &[&str] = &["us", "eu"];
+
+// enum-like settings and their supported values, the first one being the default
+const CHOICE_SETTINGS: &[(&str, &[&str])] = &[
+    ("segment_region", REGIONS),
+    ("segment_event_name_preset", &["none", "ga4"]),
+    ("segment_page_name_fallback", &["none", "title", "path"]),
+    (
+        "segment_session_id_location",
+        &["context", "properties", "none"],
+    ),
+    ("segment_send_alias", &["none", "alias", "batch"]),
+    ("segment_anonymization_ip", &["truncate", "drop"]),
+    ("segment_ip_mode", &["full", "truncated", "omitted"]),
+    (
+        "segment_anonymous_id_strategy",
+        &["edgee_id", "hashed_edgee_id", "cookieless"],
+    ),
+];
 
 #[derive(Debug, Default, Serialize)]
 pub(crate) struct SegmentPayload {
@@ -12,35 +40,47 @@ pub(crate) struct SegmentPayload {
     timestamp: DateTime<Utc>,
     #[serde(rename = "type")]
     pub(crate) event_type: String,
+    #[serde(rename = "messageId", skip_serializing_if = "Option::is_none")]
+    message_id: Option<String>,
     pub(crate) context: Context,
     #[serde(rename = "userId", skip_serializing_if = "Option::is_none")]
-    user_id: Option<String>,
+    pub(crate) user_id: Option<String>,
     #[serde(rename = "anonymousId", skip_serializing_if = "Option::is_none")]
-    anonymous_id: Option<String>,
+    pub(crate) anonymous_id: Option<String>,
+    #[serde(rename = "previousId", skip_serializing_if = "Option::is_none")]
+    pub(crate) previous_id: Option<String>,
+    #[serde(rename = "groupId", skip_serializing_if = "Option::is_none")]
+    pub(crate) group_id: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    name: Option<String>,
+    pub(crate) name: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    category: Option<String>,
+    pub(crate) category: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub(crate) properties: Option<HashMap<String, serde_json::Value>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub(crate) event: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub(crate) traits: Option<HashMap<String, serde_json::Value>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    integrations: Option<HashMap<String, serde_json::Value>>,
+    // set when the privacy profile applies, see `anonymize`
+    #[serde(skip)]
+    anonymized_id: Option<String>,
+    #[serde(skip)]
+    scrubber: Option<Scrubber>,
 }
 
 impl SegmentPayload {
-    pub fn new(edgee_event: &Event, settings: &Dict, event_type: String) -> anyhow::Result<Self> {
+    pub fn new(
+        edgee_event: &Event,
+        credentials: &HashMap<String, String>,
+        event_type: String,
+    ) -> anyhow::Result<Self> {
         let mut segment_payload = SegmentPayload {
             event_type,
             ..SegmentPayload::default()
         };
 
-        let credentials: HashMap<String, String> = settings
-            .iter()
-            .map(|(key, value)| (key.to_string(), value.to_string()))
-            .collect();
-
         if !credentials.contains_key("segment_project_id") {
             return Err(anyhow!("Segment project id is required"));
         }
@@ -49,19 +89,118 @@ impl SegmentPayload {
             return Err(anyhow!("Segment write key is required"));
         }
 
+        // fail early on a misconfigured region, endpoint or property types
+        api_host(credentials)?;
+        let coercion = Coercion::new(credentials)?;
+
+        for (key, choices) in CHOICE_SETTINGS {
+            validate_choice(credentials, key, choices)?;
+        }
+
         segment_payload.project_id = credentials.get("segment_project_id").unwrap().to_string();
+        segment_payload.scrubber = Scrubber::new(credentials);
         // Convert i64 timestamp (with microseconds) to DateTime<Utc>
         segment_payload.timestamp = Utc.timestamp_micros(edgee_event.timestamp_micros).unwrap();
 
+        // message_id, so that Segment can deduplicate retries of the same Edgee event
+        if !edgee_event.uuid.is_empty() {
+            if setting_enabled(credentials, "segment_hash_message_id") {
+                let write_key = credentials.get("segment_write_key").unwrap();
+                segment_payload.message_id =
+                    Some(sha256_hex(&format!("{write_key}:{}", edgee_event.uuid)));
+            } else {
+                segment_payload.message_id = Some(edgee_event.uuid.clone());
+            }
+        }
+
         // user_id
         if !edgee_event.context.user.user_id.is_empty() {
             segment_payload.user_id = Some(edgee_event.context.user.user_id.clone());
         }
-        // anonymous_id
-        if !edgee_event.context.user.anonymous_id.is_empty() {
-            segment_payload.anonymous_id = Some(edgee_event.context.user.anonymous_id.clone());
+        // anonymous_id, falling back to an id derived according to `segment_anonymous_id_strategy`
+        let user = &edgee_event.context.user;
+        let client = &edgee_event.context.client;
+        if !user.anonymous_id.is_empty() {
+            segment_payload.anonymous_id = Some(user.anonymous_id.clone());
         } else {
-            segment_payload.anonymous_id = Some(edgee_event.context.user.edgee_id.to_string());
+            let salt = anonymization_salt(credentials);
+            let anonymous_id = match setting_choice(credentials, "segment_anonymous_id_strategy") {
+                "hashed_edgee_id" => sha256_hex(&format!("{salt}:{}", user.edgee_id)),
+                "cookieless" => privacy::cookieless_id(
+                    salt,
+                    segment_payload.timestamp,
+                    &client.ip,
+                    &client.user_agent,
+                ),
+                _ => user.edgee_id.to_string(),
+            };
+            segment_payload.anonymous_id = Some(anonymous_id);
+        }
+
+        // context.traits: user properties stored on the device, attached to page and track calls
+        if matches!(segment_payload.event_type.as_str(), "page" | "track")
+            && !edgee_event.context.user.properties.is_empty()
+        {
+            let mut traits: HashMap<String, serde_json::Value> = edgee_event
+                .context
+                .user
+                .properties
+                .iter()
+                .map(|(key, value)| (key.clone(), coercion.coerce(key, value)))
+                .collect();
+            privacy::hash_traits(&mut traits, credentials);
+            segment_payload.context.traits = Some(coercion.unflatten(traits));
+        }
+
+        // session: Amplitude expects the session start time in epoch ms as session id,
+        // which the Edgee session id is (in seconds)
+        let session = &edgee_event.context.session;
+        let session_id_location = setting_choice(credentials, "segment_session_id_location");
+        let session_start = session
+            .session_id
+            .parse::<i64>()
+            .ok()
+            .filter(|session_start| *session_start > 0)
+            .and_then(|session_start| {
+                if session_start < 100_000_000_000 {
+                    session_start.checked_mul(1000)
+                } else {
+                    Some(session_start)
+                }
+            });
+        if let Some(session_start) = session_start.filter(|_| session_id_location != "none") {
+            segment_payload.integrations = Some(HashMap::from([(
+                "Amplitude".to_string(),
+                serde_json::json!({ "session_id": session_start }),
+            )]));
+        }
+        if session_id_location == "context" && !session.session_id.is_empty() {
+            segment_payload.context.session_id = Some(session.session_id.clone());
+            if session.session_count > 0 {
+                segment_payload.context.session_count = Some(session.session_count);
+            }
+            if !session.previous_session_id.is_empty() {
+                segment_payload.context.previous_session_id =
+                    Some(session.previous_session_id.clone());
+            }
+            segment_payload.context.session_start = Some(session.session_start);
+            segment_payload.context.first_seen = seen_at(session.first_seen);
+            segment_payload.context.last_seen = seen_at(session.last_seen);
+        }
+
+        // context.consent, for Segment Consent Management
+        let consent_categories = consent_categories(credentials)?;
+        if let Some(consent) = edgee_event.consent {
+            if !consent_categories.is_empty() {
+                let granted = consent == Consent::Granted;
+                let category_preferences = consent_categories
+                    .into_iter()
+                    .map(|(category, fixed)| (category, fixed.unwrap_or(granted)))
+                    .collect();
+                segment_payload.context.consent = Some(ConsentPreferences {
+                    category_preferences,
+                });
+            }
         }
 
         // add context.page
@@ -114,9 +253,20 @@ impl SegmentPayload {
         }
 
         // if edgee_payload.client is Some
-        if !edgee_event.context.client.ip.is_empty() {
-            segment_payload.context.ip = Some(edgee_event.context.client.ip.clone());
-        }
+        let client_ip = &edgee_event.context.client.ip;
+        // Segment geolocates the IP, so disabling geolocation omits it as well
+        let ip_mode = if setting_enabled(credentials, "segment_disable_geolocation") {
+            "omitted"
+        } else {
+            setting_choice(credentials, "segment_ip_mode")
+        };
+        segment_payload.context.ip = match ip_mode {
+            "truncated" => Some(
+                privacy::truncate_ip(client_ip).unwrap_or_else(|| privacy::OMITTED_IP.to_string()),
+            ),
+            "omitted" => Some(privacy::OMITTED_IP.to_string()),
+            _ => (!client_ip.is_empty()).then(|| client_ip.clone()),
+        };
         if !edgee_event.context.client.locale.is_empty() {
             segment_payload.context.locale = Some(edgee_event.context.client.locale.clone());
         }
@@ -147,6 +297,27 @@ impl SegmentPayload {
             segment_payload.context.screen = Some(screen);
         }
 
+        // context.location, unless geolocation is disabled
+        if !setting_enabled(credentials, "segment_disable_geolocation") {
+            let client = &edgee_event.context.client;
+            let mut location = Location::default();
+            if !client.city.is_empty() {
+                location.city = Some(client.city.clone());
+            }
+            if !client.country_name.is_empty() {
+                location.country = Some(client.country_name.clone());
+            } else if !client.country_code.is_empty() {
+                location.country = Some(client.country_code.clone());
+            }
+            if !client.region.is_empty() {
+                location.region = Some(client.region.clone());
+            }
+            // set context.location only if it has any value
+            if location.city.is_some() || location.country.is_some() || location.region.is_some() {
+                segment_payload.context.location = Some(location);
+            }
+        }
+
         if !edgee_event.context.client.timezone.is_empty() {
             segment_payload.context.timezone = Some(edgee_event.context.client.timezone.clone());
         }
@@ -156,11 +327,353 @@ impl SegmentPayload {
                 Some(edgee_event.context.client.user_agent.clone());
         }
 
+        // context.userAgentData, from the User-Agent Client Hints
+        let client = &edgee_event.context.client;
+        let mut user_agent_data = UserAgentData::default();
+        let brands = Brand::from_list(&client.user_agent_version_list);
+        if !brands.is_empty() {
+            user_agent_data.brands = Some(brands);
+        }
+        let full_version_list = Brand::from_list(&client.user_agent_full_version_list);
+        if !full_version_list.is_empty() {
+            user_agent_data.full_version_list = Some(full_version_list);
+        }
+        user_agent_data.mobile = user_agent::parse_mobile(&client.user_agent_mobile);
+        let architecture = user_agent::unquote(&client.user_agent_architecture);
+        if !architecture.is_empty() {
+            user_agent_data.architecture = Some(architecture.to_string());
+        }
+        let bitness = user_agent::unquote(&client.user_agent_bitness);
+        if !bitness.is_empty() {
+            user_agent_data.bitness = Some(bitness.to_string());
+        }
+        let model = user_agent::unquote(&client.user_agent_model);
+        if !model.is_empty() {
+            user_agent_data.model = Some(model.to_string());
+        }
+        // context.device, detected from the client hints, user-agent and screen
+        let device_info = user_agent::detect_device(
+            &client.user_agent,
+            user_agent_data.mobile,
+            &client.user_agent_model,
+            client.screen_width,
+            client.screen_height,
+        );
+        if device_info != user_agent::DeviceInfo::default() {
+            segment_payload.context.device = Some(Device {
+                type_: device_info.device_type.map(String::from),
+                model: device_info.model,
+                manufacturer: device_info.manufacturer.map(String::from),
+                ..Device::default()
+            });
+        }
+
+        // set context.userAgentData only if the browser sent any client hint
+        if user_agent_data.brands.is_some()
+            || user_agent_data.full_version_list.is_some()
+            || user_agent_data.mobile.is_some()
+            || user_agent_data.architecture.is_some()
+            || user_agent_data.bitness.is_some()
+            || user_agent_data.model.is_some()
+        {
+            if !client.os_name.is_empty() {
+                user_agent_data.platform = Some(client.os_name.clone());
+            }
+            if !client.os_version.is_empty() {
+                user_agent_data.platform_version = Some(client.os_version.clone());
+            }
+            segment_payload.context.user_agent_data = Some(user_agent_data);
+        }
+
+        // privacy profile, for visitors who have not granted their consent
+        if setting_enabled(credentials, "segment_anonymize_without_consent")
+            && matches!(
+                edgee_event.consent,
+                Some(Consent::Pending | Consent::Denied)
+            )
+        {
+            segment_payload.anonymized_id = Some(privacy::daily_hash(
+                anonymization_salt(credentials),
+                segment_payload.timestamp,
+                &edgee_event.context.user.edgee_id,
+            ));
+
+            let drop_ip = setting_choice(credentials, "segment_anonymization_ip") == "drop";
+            let truncated_ip = segment_payload
+                .context
+                .ip
+                .as_deref()
+                .filter(|_| !drop_ip)
+                .and_then(privacy::truncate_ip);
+            segment_payload.context.ip =
+                Some(truncated_ip.unwrap_or_else(|| privacy::OMITTED_IP.to_string()));
+            segment_payload.context.user_agent = segment_payload
+                .context
+                .user_agent
+                .as_deref()
+                .and_then(user_agent::browser_family_version);
+            if let Some(user_agent_data) = segment_payload.context.user_agent_data.as_mut() {
+                user_agent_data.full_version_list = None;
+                user_agent_data.platform_version = None;
+                user_agent_data.architecture = None;
+                user_agent_data.bitness = None;
+                user_agent_data.model = None;
+            }
+            if let Some(device) = segment_payload.context.device.as_mut() {
+                device.model = None;
+                device.manufacturer = None;
+            }
+            segment_payload.context.location = None;
+            segment_payload.anonymize();
+        }
+
         Ok(segment_payload)
     }
+
+    /// Whether the privacy profile applies to this payload.
+    pub(crate) fn is_anonymized(&self) -> bool {
+        self.anonymized_id.is_some()
+    }
+
+    /// Remove the user identifiers, traits and session of an anonymized payload, replacing the
+    /// anonymous id with the daily hash of the Edgee id.
+    ///
+    /// This is applied again before sending, as user calls set their ids and traits afterwards.
+    pub(crate) fn anonymize(&mut self) {
+        let Some(anonymized_id) = self.anonymized_id.as_ref() else {
+            return;
+        };
+        self.anonymous_id = Some(anonymized_id.clone());
+        self.user_id = None;
+        self.previous_id = None;
+        self.traits = None;
+        self.context.traits = None;
+        // the session ids and first/last seen times would link the daily hashes together
+        self.integrations = None;
+        self.context.session_id = None;
+        self.context.session_count = None;
+        self.context.previous_session_id = None;
+        self.context.session_start = None;
+        self.context.first_seen = None;
+        self.context.last_seen = None;
+    }
+
+    /// Use the ids of a user call, which take precedence over the ids of the event context.
+    ///
+    /// Each id falls back to its context value (and the anonymous id to the Edgee id) when the
+    /// user call does not set it.
+    pub(crate) fn set_user_ids(&mut self, user_data: &UserData) {
+        if !user_data.user_id.is_empty() {
+            self.user_id = Some(user_data.user_id.clone());
+        }
+        if !user_data.anonymous_id.is_empty() {
+            self.anonymous_id = Some(user_data.anonymous_id.clone());
+        }
+    }
+
+    /// Redact the personal data of the page, campaign, properties and traits, when scrubbing is
+    /// enabled.
+    ///
+    /// This is applied before sending, once all the properties and traits are set.
+    pub(crate) fn scrub(&mut self) {
+        let Some(scrubber) = self.scrubber.as_ref() else {
+            return;
+        };
+        for value in [&mut self.name, &mut self.category].into_iter().flatten() {
+            *value = scrubber.scrub(value);
+        }
+        if let Some(campaign) = self.context.campaign.as_mut() {
+            for (key, value) in [
+                ("utm_campaign", &mut campaign.name),
+                ("utm_source", &mut campaign.source),
+                ("utm_medium", &mut campaign.medium),
+                ("utm_term", &mut campaign.term),
+                ("utm_content", &mut campaign.content),
+            ] {
+                if let Some(value) = value.as_mut() {
+                    *value = scrubber.scrub_param(key, value);
+                }
+            }
+        }
+        if let Some(page) = self.context.page.as_mut() {
+            for value in [
+                &mut page.path,
+                &mut page.referrer,
+                &mut page.search,
+                &mut page.title,
+                &mut page.url,
+            ]
+            .into_iter()
+            .flatten()
+            {
+                *value = scrubber.scrub(value);
+            }
+        }
+        for map in [
+            self.properties.as_mut(),
+            self.traits.as_mut(),
+            self.context.traits.as_mut(),
+        ]
+        .into_iter()
+        .flatten()
+        {
+            scrubber.scrub_map(map);
+        }
+    }
+
+    /// Start an additional message derived from the same Edgee event, sharing the ids and context
+    /// of this one, its messageId made unique with the suffix.
+    pub(crate) fn derive(&self, event_type: &str, suffix: &str) -> SegmentPayload {
+        let mut context = self.context.clone();
+        // only page and track calls carry the stored user traits
+        if !matches!(event_type, "page" | "track") {
+            context.traits = None;
+        }
+        SegmentPayload {
+            project_id: self.project_id.clone(),
+            timestamp: self.timestamp,
+            event_type: event_type.to_string(),
+            message_id: self
+                .message_id
+                .as_ref()
+                .map(|message_id| format!("{message_id}-{suffix}")),
+            context,
+            user_id: self.user_id.clone(),
+            anonymous_id: self.anonymous_id.clone(),
+            integrations: self.integrations.clone(),
+            anonymized_id: self.anonymized_id.clone(),
+            scrubber: self.scrubber.clone(),
+            ..SegmentPayload::default()
+        }
+    }
+
+    /// Path of the Segment HTTP Tracking API endpoint matching this payload's call type.
+    pub(crate) fn endpoint(&self) -> &'static str {
+        match self.event_type.as_str() {
+            "page" => "/v1/page",
+            "identify" => "/v1/identify",
+            "group" => "/v1/group",
+            "alias" => "/v1/alias",
+            "screen" => "/v1/screen",
+            _ => "/v1/track",
+        }
+    }
 }
 
-#[derive(Serialize, Debug, Default)]
+/// Parse `segment_consent_categories`: comma-separated Segment consent categories, either
+/// following the Edgee consent (`Analytics`) or with a fixed preference (`Necessary:true`).
+fn consent_categories(
+    settings: &HashMap<String, String>,
+) -> anyhow::Result<Vec<(String, Option<bool>)>> {
+    let Some(categories) = settings.get("segment_consent_categories") else {
+        return Ok(vec![]);
+    };
+
+    let mut consent_categories = vec![];
+    for category in categories
+        .split(',')
+        .map(str::trim)
+        .filter(|c| !c.is_empty())
+    {
+        let consent_category = match category.split_once(':') {
+            None => (category.to_string(), None),
+            Some((category, fixed)) => match fixed.trim().to_lowercase().as_str() {
+                "true" => (category.trim().to_string(), Some(true)),
+                "false" => (category.trim().to_string(), Some(false)),
+                _ => {
+                    return Err(anyhow!(
+                        "Segment consent category `{category}` preference must be true or false"
+                    ))
+                }
+            },
+        };
+        consent_categories.push(consent_category);
+    }
+    Ok(consent_categories)
+}
+
+/// Salt of the hashed visitor ids: `segment_anonymization_salt`, or else the write key.
+fn anonymization_salt(settings: &HashMap<String, String>) -> &str {
+    settings
+        .get("segment_anonymization_salt")
+        .filter(|salt| !salt.is_empty())
+        .or(settings.get("segment_write_key"))
+        .map(String::as_str)
+        .unwrap_or_default()
+}
+
+/// Lowercased value of an enum-like setting, which must be one of the choices (the first one
+/// being the default, when the setting is not set).
+fn validate_choice(
+    settings: &HashMap<String, String>,
+    key: &str,
+    choices: &[&'static str],
+) -> anyhow::Result<&'static str> {
+    let Some(value) = settings
+        .get(key)
+        .map(|value| value.trim().to_lowercase())
+        .filter(|value| !value.is_empty())
+    else {
+        return Ok(choices[0]);
+    };
+    choices
+        .iter()
+        .find(|choice| **choice == value)
+        .copied()
+        .ok_or_else(|| {
+            anyhow!(
+                "Segment {} `{value}` is not supported ({})",
+                key.trim_start_matches("segment_").replace('_', " "),
+                choices.join(", ")
+            )
+        })
+}
+
+/// Value of one of the `CHOICE_SETTINGS`, already validated by `SegmentPayload::new`.
+pub(crate) fn setting_choice(settings: &HashMap<String, String>, key: &str) -> &'static str {
+    let (_, choices) = CHOICE_SETTINGS
+        .iter()
+        .find(|(setting, _)| *setting == key)
+        .expect("not an enum-like setting");
+    validate_choice(settings, key, choices).unwrap_or(choices[0])
+}
+
+/// Whether a boolean setting is set to `true`.
+pub(crate) fn setting_enabled(settings: &HashMap<String, String>, key: &str) -> bool {
+    settings
+        .get(key)
+        .is_some_and(|value| value.eq_ignore_ascii_case("true"))
+}
+
+/// Time of an Edgee `first_seen`/`last_seen` (epoch seconds), unless it is unset (`<= 0`).
+pub(crate) fn seen_at(seconds: i64) -> Option<DateTime<Utc>> {
+    if seconds <= 0 {
+        return None;
+    }
+    Utc.timestamp_opt(seconds, 0).single()
+}
+
+/// Lowercase hexadecimal SHA-256 digest of the input.
+pub(crate) fn sha256_hex(input: &str) -> String {
+    format!("{:x}", Sha256::digest(input.as_bytes()))
+}
+
+/// Resolve the Segment API host from `segment_endpoint` (if set) or `segment_region`.
+pub(crate) fn api_host(settings: &HashMap<String, String>) -> anyhow::Result<String> {
+    if let Some(endpoint) = settings.get("segment_endpoint").filter(|e| !e.is_empty()) {
+        if !endpoint.starts_with("https://") && !endpoint.starts_with("http://") {
+            return Err(anyhow!("Segment endpoint must be an http(s) URL"));
+        }
+        return Ok(endpoint.trim_end_matches('/').to_string());
+    }
+
+    match validate_choice(settings, "segment_region", REGIONS)? {
+        "eu" => Ok(EU_API_HOST.to_string()),
+        _ => Ok(US_API_HOST.to_string()),
+    }
+}
+
+#[derive(Serialize, Debug, Clone, Default)]
 pub struct Context {
     #[serde(skip_serializing_if = "Option::is_none")]
     active: Option<bool>,
@@ -169,6 +682,8 @@ pub struct Context {
     #[serde(skip_serializing_if = "Option::is_none")]
     campaign: Option<Campaign>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    consent: Option<ConsentPreferences>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     device: Option<Device>,
     #[serde(skip_serializing_if = "Option::is_none")]
     ip: Option<String>,
@@ -177,6 +692,8 @@ pub struct Context {
     #[serde(skip_serializing_if = "Option::is_none")]
     locale: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    location: Option<Location>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     network: Option<Network>,
     #[serde(skip_serializing_if = "Option::is_none")]
     os: Option<Os>,
@@ -188,13 +705,29 @@ pub struct Context {
     screen: Option<Screen>,
     #[serde(skip_serializing_if = "Option::is_none")]
     group_id: Option<String>,
+    #[serde(rename = "sessionId", skip_serializing_if = "Option::is_none")]
+    session_id: Option<String>,
+    #[serde(rename = "sessionCount", skip_serializing_if = "Option::is_none")]
+    session_count: Option<u32>,
+    #[serde(rename = "previousSessionId", skip_serializing_if = "Option::is_none")]
+    previous_session_id: Option<String>,
+    #[serde(rename = "sessionStart", skip_serializing_if = "Option::is_none")]
+    session_start: Option<bool>,
+    #[serde(rename = "firstSeen", skip_serializing_if = "Option::is_none")]
+    first_seen: Option<DateTime<Utc>>,
+    #[serde(rename = "lastSeen", skip_serializing_if = "Option::is_none")]
+    last_seen: Option<DateTime<Utc>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     timezone: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) traits: Option<HashMap<String, serde_json::Value>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     user_agent: Option<String>,
+    #[serde(rename = "userAgentData", skip_serializing_if = "Option::is_none")]
+    user_agent_data: Option<UserAgentData>,
 }
 
-#[derive(Serialize, Debug)]
+#[derive(Serialize, Debug, Clone)]
 struct App {
     #[serde(skip_serializing_if = "Option::is_none")]
     name: Option<String>,
@@ -206,7 +739,7 @@ struct App {
     namespace: Option<String>,
 }
 
-#[derive(Serialize, Debug, Default)]
+#[derive(Serialize, Debug, Clone, Default)]
 struct Campaign {
     #[serde(skip_serializing_if = "Option::is_none")]
     name: Option<String>,
@@ -220,7 +753,13 @@ struct Campaign {
     content: Option<String>,
 }
 
-#[derive(Serialize, Debug)]
+#[derive(Serialize, Debug, Clone)]
+struct ConsentPreferences {
+    #[serde(rename = "categoryPreferences")]
+    category_preferences: HashMap<String, bool>,
+}
+
+#[derive(Serialize, Debug, Clone, Default)]
 struct Device {
     #[serde(skip_serializing_if = "Option::is_none")]
     id: Option<String>,
@@ -234,13 +773,13 @@ struct Device {
     model: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     name: Option<String>,
-    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(rename = "type", skip_serializing_if = "Option::is_none")]
     type_: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     token: Option<String>,
 }
 
-#[derive(Serialize, Debug)]
+#[derive(Serialize, Debug, Clone)]
 struct Library {
     #[serde(skip_serializing_if = "Option::is_none")]
     name: Option<String>,
@@ -248,7 +787,17 @@ struct Library {
     version: Option<String>,
 }
 
-#[derive(Serialize, Debug)]
+#[derive(Serialize, Debug, Clone, Default)]
+struct Location {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    city: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    country: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    region: Option<String>,
+}
+
+#[derive(Serialize, Debug, Clone)]
 struct Network {
     #[serde(skip_serializing_if = "Option::is_none")]
     bluetooth: Option<bool>,
@@ -260,7 +809,7 @@ struct Network {
     wifi: Option<bool>,
 }
 
-#[derive(Serialize, Debug, Default)]
+#[derive(Serialize, Debug, Clone, Default)]
 struct Os {
     #[serde(skip_serializing_if = "Option::is_none")]
     name: Option<String>,
@@ -268,7 +817,7 @@ struct Os {
     version: Option<String>,
 }
 
-#[derive(Serialize, Debug, Default)]
+#[derive(Serialize, Debug, Clone, Default)]
 pub(crate) struct Page {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub(crate) path: Option<String>,
@@ -282,7 +831,7 @@ pub(crate) struct Page {
     pub(crate) url: Option<String>,
 }
 
-#[derive(Serialize, Debug)]
+#[derive(Serialize, Debug, Clone)]
 struct Referrer {
     #[serde(skip_serializing_if = "Option::is_none")]
     id: Option<String>,
@@ -290,7 +839,7 @@ struct Referrer {
     type_: Option<String>,
 }
 
-#[derive(Serialize, Debug, Default)]
+#[derive(Serialize, Debug, Clone, Default)]
 struct Screen {
     #[serde(skip_serializing_if = "Option::is_none")]
     width: Option<u32>,
@@ -299,3 +848,38 @@ struct Screen {
     #[serde(skip_serializing_if = "Option::is_none")]
     density: Option<f32>,
 }
+
+#[derive(Serialize, Debug, Clone, Default)]
+struct UserAgentData {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    brands: Option<Vec<Brand>>,
+    #[serde(rename = "fullVersionList", skip_serializing_if = "Option::is_none")]
+    full_version_list: Option<Vec<Brand>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    mobile: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    platform: Option<String>,
+    #[serde(rename = "platformVersion", skip_serializing_if = "Option::is_none")]
+    platform_version: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    architecture: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    bitness: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    model: Option<String>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+struct Brand {
+    brand: String,
+    version: String,
+}
+
+impl Brand {
+    fn from_list(value: &str) -> Vec<Brand> {
+        user_agent::parse_brand_list(value)
+            .into_iter()
+            .map(|(brand, version)| Brand { brand, version })
+            .collect()
+    }
+}
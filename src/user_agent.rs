@@ -0,0 +1,441 @@
+// Offline device detection ruleset: user-agent tokens, checked in order
+const TABLET_TOKENS: &[&str] = &["iPad", "Tablet", "Kindle", "Silk/", "PlayBook"];
+const MOBILE_TOKENS: &[&str] = &[
+    "Mobi",
+    "iPhone",
+    "iPod",
+    "Windows Phone",
+    "BlackBerry",
+    "BB10",
+    "Opera Mini",
+];
+
+// model or user-agent token => manufacturer
+const MANUFACTURERS: &[(&str, &str)] = &[
+    ("iPhone", "Apple"),
+    ("iPad", "Apple"),
+    ("iPod", "Apple"),
+    ("Macintosh", "Apple"),
+    ("Pixel", "Google"),
+    ("Nexus", "Google"),
+    ("SM-", "Samsung"),
+    ("GT-", "Samsung"),
+    ("Galaxy", "Samsung"),
+    ("SAMSUNG", "Samsung"),
+    ("Redmi", "Xiaomi"),
+    ("POCO", "Xiaomi"),
+    ("Xiaomi", "Xiaomi"),
+    ("HUAWEI", "Huawei"),
+    ("Huawei", "Huawei"),
+    ("HONOR", "Honor"),
+    ("ONEPLUS", "OnePlus"),
+    ("OnePlus", "OnePlus"),
+    ("CPH", "OPPO"),
+    ("OPPO", "OPPO"),
+    ("RMX", "realme"),
+    ("vivo", "vivo"),
+    ("moto", "Motorola"),
+    ("Motorola", "Motorola"),
+    ("Nokia", "Nokia"),
+    ("LM-", "LG"),
+    ("LG-", "LG"),
+    ("XQ-", "Sony"),
+    ("Xperia", "Sony"),
+    ("Lenovo", "Lenovo"),
+    ("Kindle", "Amazon"),
+    ("KF", "Amazon"),
+    ("Silk/", "Amazon"),
+    ("Windows Phone", "Microsoft"),
+    ("BlackBerry", "BlackBerry"),
+];
+
+// user-agent token => browser family, checked in order as most browsers also mention the
+// engines they are built on (e.g. Edge user-agents contain `Chrome/` and `Safari/`)
+const BROWSER_FAMILIES: &[(&str, &str)] = &[
+    ("Edg/", "Edge"),
+    ("EdgA/", "Edge"),
+    ("EdgiOS/", "Edge"),
+    ("OPR/", "Opera"),
+    ("SamsungBrowser/", "Samsung Internet"),
+    ("Firefox/", "Firefox"),
+    ("FxiOS/", "Firefox"),
+    ("CriOS/", "Chrome"),
+    ("Chrome/", "Chrome"),
+    ("Version/", "Safari"),
+];
+
+/// Device properties detected from the client data.
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct DeviceInfo {
+    pub(crate) device_type: Option<&'static str>,
+    pub(crate) model: Option<String>,
+    pub(crate) manufacturer: Option<&'static str>,
+}
+
+/// Detect the device type (`mobile`, `tablet` or `desktop`), model and manufacturer from
+/// the client hints, the user-agent string and, as a last resort, the screen dimensions.
+pub(crate) fn detect_device(
+    user_agent: &str,
+    mobile_hint: Option<bool>,
+    model_hint: &str,
+    screen_width: i32,
+    screen_height: i32,
+) -> DeviceInfo {
+    let device_type = if mobile_hint == Some(true) {
+        Some("mobile")
+    } else if TABLET_TOKENS.iter().any(|token| user_agent.contains(token))
+        || (user_agent.contains("Android") && !user_agent.contains("Mobile"))
+    {
+        Some("tablet")
+    } else if MOBILE_TOKENS.iter().any(|token| user_agent.contains(token)) {
+        Some("mobile")
+    } else if !user_agent.is_empty() || mobile_hint == Some(false) {
+        Some("desktop")
+    } else {
+        device_type_from_screen(screen_width, screen_height)
+    };
+
+    let model_hint = unquote(model_hint);
+    let model = if !model_hint.is_empty() {
+        Some(model_hint.to_string())
+    } else if let Some(apple_model) = ["iPhone", "iPad", "iPod"]
+        .into_iter()
+        .find(|model| user_agent.contains(model))
+    {
+        Some(apple_model.to_string())
+    } else {
+        android_model(user_agent)
+    };
+
+    let manufacturer = MANUFACTURERS
+        .iter()
+        .find(|(token, _)| model.as_deref().is_some_and(|model| model.contains(token)))
+        .or_else(|| {
+            MANUFACTURERS
+                .iter()
+                .find(|(token, _)| user_agent.contains(token))
+        })
+        .map(|(_, manufacturer)| *manufacturer);
+
+    DeviceInfo {
+        device_type,
+        model,
+        manufacturer,
+    }
+}
+
+/// Guess the device type from the screen size in CSS pixels.
+fn device_type_from_screen(width: i32, height: i32) -> Option<&'static str> {
+    if width <= 0 || height <= 0 {
+        return None;
+    }
+    let (shortest, longest) = (width.min(height), width.max(height));
+    if shortest < 600 {
+        Some("mobile")
+    } else if longest < 1280 {
+        Some("tablet")
+    } else {
+        Some("desktop")
+    }
+}
+
+/// Extract the model of an Android user-agent, e.g. `Pixel 8` from
+/// `Mozilla/5.0 (Linux; Android 14; Pixel 8 Build/AP1A) ...`.
+fn android_model(user_agent: &str) -> Option<String> {
+    let start = user_agent.find('(')? + 1;
+    let end = start + user_agent[start..].find(')')?;
+    let mut tokens = user_agent[start..end].split(';').map(str::trim);
+    tokens.find(|token| token.starts_with("Android"))?;
+    let model = tokens.next()?;
+    let model = model.split(" Build/").next().unwrap_or(model).trim();
+    // reduced user-agents replace the model with "K"
+    (!model.is_empty() && model != "K" && model != "wv").then(|| model.to_string())
+}
+
+/// Parse a User-Agent Client Hints brand list into `(brand, version)` pairs.
+///
+/// Both the `Sec-CH-UA` header format (`"Chromium";v="128", "Google Chrome";v="128"`) and
+/// the pipe-separated format (`Chromium;128|Google Chrome;128`) are supported.
+pub(crate) fn parse_brand_list(value: &str) -> Vec<(String, String)> {
+    if value.contains(";v=") {
+        parse_header_brand_list(value)
+    } else {
+        value
+            .split('|')
+            .filter_map(|entry| {
+                // brands may contain `;`, e.g. "Not;A=Brand;24"
+                let (brand, version) = entry.rsplit_once(';')?;
+                let (brand, version) = (unquote(brand), unquote(version));
+                (!brand.is_empty()).then(|| (brand.to_string(), version.to_string()))
+            })
+            .collect()
+    }
+}
+
+fn parse_header_brand_list(value: &str) -> Vec<(String, String)> {
+    let mut brands = vec![];
+    let mut rest = value;
+    while let Some((brand, after_brand)) = next_quoted(rest) {
+        let Some(after_param) = after_brand.trim_start().strip_prefix(";v=") else {
+            break;
+        };
+        let Some((version, after_version)) = next_quoted(after_param) else {
+            break;
+        };
+        if !brand.is_empty() {
+            brands.push((brand.to_string(), version.to_string()));
+        }
+        rest = after_version;
+    }
+    brands
+}
+
+/// Split the first double-quoted string of the value from what follows it.
+fn next_quoted(value: &str) -> Option<(&str, &str)> {
+    let start = value.find('"')? + 1;
+    let end = start + value[start..].find('"')?;
+    Some((&value[start..end], &value[end + 1..]))
+}
+
+/// Reduce a user-agent to its browser family and major version, e.g. `Chrome/128`.
+pub(crate) fn browser_family_version(user_agent: &str) -> Option<String> {
+    BROWSER_FAMILIES.iter().find_map(|(token, family)| {
+        if *token == "Version/" && !user_agent.contains("Safari/") {
+            return None;
+        }
+        let start = user_agent.find(token)? + token.len();
+        let major: String = user_agent[start..]
+            .chars()
+            .take_while(|c| c.is_ascii_digit())
+            .collect();
+        (!major.is_empty()).then(|| format!("{family}/{major}"))
+    })
+}
+
+/// Parse the `Sec-CH-UA-Mobile` hint (`?1`, `?0`, or a boolean).
+pub(crate) fn parse_mobile(value: &str) -> Option<bool> {
+    match value.trim().to_lowercase().as_str() {
+        "?1" | "1" | "true" => Some(true),
+        "?0" | "0" | "false" => Some(false),
+        _ => None,
+    }
+}
+
+/// Remove the surrounding double quotes of a structured header string, e.g. `"x86"`.
+pub(crate) fn unquote(value: &str) -> &str {
+    let value = value.trim();
+    value
+        .strip_prefix('"')
+        .and_then(|value| value.strip_suffix('"'))
+        .unwrap_or(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn brands(brands: &[(&str, &str)]) -> Vec<(String, String)> {
+        brands
+            .iter()
+            .map(|(brand, version)| (brand.to_string(), version.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn header_brand_list() {
+        assert_eq!(
+            parse_brand_list(
+                r#""Chromium";v="128", "Not;A=Brand";v="24", "Google Chrome";v="128""#
+            ),
+            brands(&[
+                ("Chromium", "128"),
+                ("Not;A=Brand", "24"),
+                ("Google Chrome", "128")
+            ])
+        );
+        assert_eq!(
+            parse_brand_list(r#""Chromium";v="128.0.6613.120", "Not_A Brand";v="8.0.0.0""#),
+            brands(&[("Chromium", "128.0.6613.120"), ("Not_A Brand", "8.0.0.0")])
+        );
+    }
+
+    #[test]
+    fn pipe_brand_list() {
+        assert_eq!(
+            parse_brand_list("Chromium;128.0.6613.120|Not;A=Brand;24.0.0.0"),
+            brands(&[("Chromium", "128.0.6613.120"), ("Not;A=Brand", "24.0.0.0")])
+        );
+    }
+
+    #[test]
+    fn invalid_brand_list() {
+        assert_eq!(parse_brand_list(""), brands(&[]));
+        assert_eq!(parse_brand_list("abc"), brands(&[]));
+        assert_eq!(parse_brand_list(r#""Chromium";v="#), brands(&[]));
+        assert_eq!(parse_brand_list(";128"), brands(&[]));
+    }
+
+    #[test]
+    fn detect_mobile_devices() {
+        assert_eq!(
+            detect_device(
+                "Mozilla/5.0 (iPhone; CPU iPhone OS 17_5 like Mac OS X) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/17.5 Mobile/15E148 Safari/604.1",
+                None,
+                "",
+                390,
+                844,
+            ),
+            DeviceInfo {
+                device_type: Some("mobile"),
+                model: Some("iPhone".to_string()),
+                manufacturer: Some("Apple"),
+            }
+        );
+        assert_eq!(
+            detect_device(
+                "Mozilla/5.0 (Linux; Android 10; SM-G973F Build/QP1A.190711.020; wv) AppleWebKit/537.36 (KHTML, like Gecko) Version/4.0 Chrome/128.0.0.0 Mobile Safari/537.36",
+                None,
+                "",
+                0,
+                0,
+            ),
+            DeviceInfo {
+                device_type: Some("mobile"),
+                model: Some("SM-G973F".to_string()),
+                manufacturer: Some("Samsung"),
+            }
+        );
+        // reduced user-agent, with client hints
+        assert_eq!(
+            detect_device(
+                "Mozilla/5.0 (Linux; Android 10; K) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/128.0.0.0 Mobile Safari/537.36",
+                Some(true),
+                r#""Pixel 8""#,
+                412,
+                915,
+            ),
+            DeviceInfo {
+                device_type: Some("mobile"),
+                model: Some("Pixel 8".to_string()),
+                manufacturer: Some("Google"),
+            }
+        );
+    }
+
+    #[test]
+    fn detect_tablets() {
+        assert_eq!(
+            detect_device(
+                "Mozilla/5.0 (iPad; CPU OS 17_5 like Mac OS X) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/17.5 Mobile/15E148 Safari/604.1",
+                None,
+                "",
+                0,
+                0,
+            )
+            .device_type,
+            Some("tablet")
+        );
+        assert_eq!(
+            detect_device(
+                "Mozilla/5.0 (Linux; Android 13; SM-X710) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/128.0.0.0 Safari/537.36",
+                Some(false),
+                "",
+                0,
+                0,
+            ),
+            DeviceInfo {
+                device_type: Some("tablet"),
+                model: Some("SM-X710".to_string()),
+                manufacturer: Some("Samsung"),
+            }
+        );
+    }
+
+    #[test]
+    fn detect_desktops() {
+        assert_eq!(
+            detect_device(
+                "Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/128.0.0.0 Safari/537.36",
+                Some(false),
+                "",
+                1440,
+                900,
+            ),
+            DeviceInfo {
+                device_type: Some("desktop"),
+                model: None,
+                manufacturer: Some("Apple"),
+            }
+        );
+        assert_eq!(
+            detect_device(
+                "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/128.0.0.0 Safari/537.36",
+                None,
+                "",
+                0,
+                0,
+            ),
+            DeviceInfo {
+                device_type: Some("desktop"),
+                model: None,
+                manufacturer: None,
+            }
+        );
+    }
+
+    #[test]
+    fn detect_from_screen() {
+        assert_eq!(
+            detect_device("", None, "", 390, 844).device_type,
+            Some("mobile")
+        );
+        assert_eq!(
+            detect_device("", None, "", 1024, 768).device_type,
+            Some("tablet")
+        );
+        assert_eq!(
+            detect_device("", None, "", 1920, 1080).device_type,
+            Some("desktop")
+        );
+        assert_eq!(detect_device("", None, "", 0, 0), DeviceInfo::default());
+    }
+
+    #[test]
+    fn browser_families() {
+        assert_eq!(
+            browser_family_version("Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/128.0.0.0 Safari/537.36"),
+            Some("Chrome/128".to_string())
+        );
+        assert_eq!(
+            browser_family_version("Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/128.0.0.0 Safari/537.36 Edg/128.0.2739.42"),
+            Some("Edge/128".to_string())
+        );
+        assert_eq!(
+            browser_family_version("Mozilla/5.0 (iPhone; CPU iPhone OS 17_5 like Mac OS X) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/17.5 Mobile/15E148 Safari/604.1"),
+            Some("Safari/17".to_string())
+        );
+        assert_eq!(
+            browser_family_version(
+                "Mozilla/5.0 (X11; Linux x86_64; rv:130.0) Gecko/20100101 Firefox/130.0"
+            ),
+            Some("Firefox/130".to_string())
+        );
+        assert_eq!(browser_family_version("curl/8.7.1"), None);
+        assert_eq!(browser_family_version("Chrome"), None);
+    }
+
+    #[test]
+    fn mobile() {
+        assert_eq!(parse_mobile("?1"), Some(true));
+        assert_eq!(parse_mobile("0"), Some(false));
+        assert_eq!(parse_mobile("mobile"), None);
+    }
+
+    #[test]
+    fn unquoted() {
+        assert_eq!(unquote(r#""x86""#), "x86");
+        assert_eq!(unquote("64"), "64");
+        assert_eq!(unquote(r#"""#), r#"""#);
+    }
+}